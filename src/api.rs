@@ -0,0 +1,351 @@
+use log::info;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt;
+
+use crate::secret;
+use crate::transport::{Body, Method, Request, Response, Transport, TransportError};
+use crate::types::{Error, NSDef, NSResponse, Token};
+
+// where the Platform API is mounted, eg. 'https://api.example.com' or
+// 'http://127.0.0.1:8080/platform/v2'. every endpoint is derived from it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseUrl {
+    url: String,
+    // negotiated API version, which prefixes the namespace endpoints
+    version: Option<String>,
+}
+
+impl BaseUrl {
+    // a bare hostname, as originally accepted, is served over https
+    pub fn parse(inp: &str) -> Result<BaseUrl, Error> {
+        let inp = inp.trim();
+        let (scheme, rest) = inp.split_once("://").unwrap_or(("https", inp));
+        let rest = rest.trim_end_matches('/');
+        if scheme != "http" && scheme != "https" {
+            Err(Error::Unknown(format!(
+                "Unsupported Platform API URL '{}', expected http or https rather than '{}'",
+                inp, scheme
+            )))
+        } else if rest.is_empty() {
+            Err(Error::Unknown(format!(
+                "Platform API URL '{}' has no host",
+                inp
+            )))
+        } else {
+            Ok(BaseUrl {
+                url: format!("{}://{}", scheme, rest),
+                version: None,
+            })
+        }
+    }
+
+    pub fn with_version(&self, version: &str) -> BaseUrl {
+        BaseUrl {
+            url: self.url.clone(),
+            version: Some(version.to_string()),
+        }
+    }
+
+    // an unversioned endpoint such as 'version' or 'openapi.json'
+    pub fn endpoint_url(&self, path: &str) -> String {
+        format!("{}/{}", self.url, path)
+    }
+
+    pub fn namespace_url(&self) -> String {
+        match &self.version {
+            Some(version) => format!("{}/{}/namespace", self.url, version),
+            None => format!("{}/namespace", self.url),
+        }
+    }
+
+    fn namespace_item_url(&self, namespace: &str) -> String {
+        format!(
+            "{}/{}",
+            self.namespace_url(),
+            urlencoding::encode(namespace)
+        )
+    }
+}
+
+impl fmt::Display for BaseUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.url)
+    }
+}
+
+fn send(transport: &dyn Transport, req: Request) -> Result<Response, Error> {
+    transport.send(req).map_err(|e| match e {
+        TransportError::Timeout => Error::APITimeout,
+        TransportError::Other(e) => Error::Unknown(format!(
+            "Got an unknown error communicating with the Platform API: {}",
+            e
+        )),
+    })
+}
+
+fn decode<T: DeserializeOwned>(resp: Response) -> Result<T, Error> {
+    if resp.is_success() {
+        serde_json::from_str(&resp.body)
+            .map_err(|e| Error::Unknown(format!("Error decoding API Response: {}", e)))
+    } else {
+        Err(Error::Api(resp.status, resp.body))
+    }
+}
+
+// fetch an unversioned discovery document, returning None if the API
+// doesn't serve it
+pub fn discover(
+    transport: &dyn Transport,
+    base: &BaseUrl,
+    token: &Token,
+    path: &str,
+) -> Result<Option<Value>, Error> {
+    let url = base.endpoint_url(path);
+    info!("probing API capabilities at {}", url);
+    let resp = send(transport, Request::new(Method::Get, url).bearer(token))?;
+    if resp.status == 404 {
+        Ok(None)
+    } else {
+        decode(resp).map(Some)
+    }
+}
+
+pub fn create(
+    transport: &dyn Transport,
+    base: &BaseUrl,
+    token: &Token,
+    payload: &NSDef,
+) -> Result<NSResponse, Error> {
+    let url = base.namespace_url();
+    let body = serde_json::to_value(payload)
+        .map_err(|e| Error::Unknown(format!("Error encoding request body: {}", e)))?;
+    let mut logged = body.clone();
+    secret::redact(&mut logged);
+    info!("submitting request body to {}: {}", url, logged);
+    let body = body.to_string();
+    decode(send(
+        transport,
+        Request::new(Method::Post, url)
+            .bearer(token)
+            .body(Body::Json(body)),
+    )?)
+}
+
+// fetch the live definition of a namespace, returning None if it doesn't exist
+pub fn get(
+    transport: &dyn Transport,
+    base: &BaseUrl,
+    token: &Token,
+    namespace: &str,
+) -> Result<Option<Value>, Error> {
+    let url = base.namespace_item_url(namespace);
+    info!("fetching namespace from {}", url);
+    let resp = send(transport, Request::new(Method::Get, url).bearer(token))?;
+    if resp.status == 404 {
+        Ok(None)
+    } else {
+        decode(resp).map(Some)
+    }
+}
+
+pub fn delete(
+    transport: &dyn Transport,
+    base: &BaseUrl,
+    token: &Token,
+    namespace: &str,
+) -> Result<(), Error> {
+    let url = base.namespace_item_url(namespace);
+    info!("deleting namespace at {}", url);
+    let resp = send(transport, Request::new(Method::Delete, url).bearer(token))?;
+    if resp.is_success() {
+        Ok(())
+    } else {
+        Err(Error::Api(resp.status, resp.body))
+    }
+}
+
+// list the namespaces belonging to a product key
+pub fn list(
+    transport: &dyn Transport,
+    base: &BaseUrl,
+    token: &Token,
+    productkey: &str,
+) -> Result<Vec<Value>, Error> {
+    let url = format!(
+        "{}?productkey={}",
+        base.namespace_url(),
+        urlencoding::encode(productkey)
+    );
+    info!("listing namespaces for {} from {}", productkey, url);
+    let resp: Value = decode(send(
+        transport,
+        Request::new(Method::Get, url).bearer(token),
+    )?)?;
+    match resp {
+        Value::Array(items) => Ok(items),
+        Value::Object(mut obj) => match obj.remove("namespaces") {
+            Some(Value::Array(items)) => Ok(items),
+            _ => Err(Error::Unknown(
+                "Error decoding API Response: expected a list of namespaces".to_string(),
+            )),
+        },
+        _ => Err(Error::Unknown(
+            "Error decoding API Response: expected a list of namespaces".to_string(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::transport::fake::FakeTransport;
+    use crate::types::NSDefBuilder;
+
+    fn base() -> BaseUrl {
+        BaseUrl::parse("api.example.com").unwrap()
+    }
+
+    fn token() -> Token {
+        serde_json::from_str(r#"{"token_type": "Bearer", "access_token": "secret"}"#).unwrap()
+    }
+
+    fn payload() -> NSDef {
+        NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("24h")
+            .cluster("cluster")
+            .namespace("test")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn create_success() {
+        let transport = FakeTransport::new().respond(
+            200,
+            r#"{"message": "created", "namespace": "demo-test", "expiry": "2021-08-03T09:49:17Z",
+                "owner": "team-a", "dashboard_url": "https://grafana/demo-test", "shard": 3}"#,
+        );
+        let resp = create(&transport, &base(), &token(), &payload()).unwrap();
+        assert_eq!(resp.namespace, "demo-test");
+        assert_eq!(resp.expiry, "2021-08-03T09:49:17Z");
+        assert_eq!(resp.additional_properties["shard"], 3);
+        let shown = resp.to_string();
+        assert!(shown.contains("\nowner: team-a"));
+        assert!(shown.contains("\ndashboard_url: https://grafana/demo-test"));
+        assert!(!shown.contains("shard"));
+        let reqs = transport.requests();
+        assert_eq!(reqs.len(), 1);
+        assert_eq!(reqs[0].method, Method::Post);
+        assert_eq!(reqs[0].url, "https://api.example.com/namespace");
+        assert_eq!(reqs[0].bearer.as_ref().map(Secret::expose), Some("secret"));
+        match &reqs[0].body {
+            Some(Body::Json(body)) => {
+                let body: Value = serde_json::from_str(body).unwrap();
+                assert_eq!(body["namespace"], "test");
+                assert_eq!(body["productkey"], "demo");
+            }
+            other => panic!("unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn create_client_error() {
+        let transport = FakeTransport::new().respond(400, "bad ttl");
+        match create(&transport, &base(), &token(), &payload()) {
+            Err(Error::Api(400, body)) => assert_eq!(body, "bad ttl"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn create_server_error() {
+        let transport = FakeTransport::new().respond(503, "unavailable");
+        match create(&transport, &base(), &token(), &payload()) {
+            Err(Error::Api(503, body)) => assert_eq!(body, "unavailable"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn create_timeout() {
+        let transport = FakeTransport::new().fail(TransportError::Timeout);
+        match create(&transport, &base(), &token(), &payload()) {
+            Err(Error::APITimeout) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn create_malformed_json() {
+        let transport = FakeTransport::new().respond(200, "{not json");
+        match create(&transport, &base(), &token(), &payload()) {
+            Err(Error::Unknown(msg)) => assert!(msg.starts_with("Error decoding API Response")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn get_not_found() {
+        let transport = FakeTransport::new().respond(404, "");
+        let resp = get(&transport, &base(), &token(), "demo-test").unwrap();
+        assert!(resp.is_none());
+        assert_eq!(
+            transport.requests()[0].url,
+            "https://api.example.com/namespace/demo-test"
+        );
+    }
+
+    #[test]
+    fn list_wrapped_namespaces() {
+        let transport =
+            FakeTransport::new().respond(200, r#"{"namespaces": [{"namespace": "demo-a"}]}"#);
+        let items = list(&transport, &base(), &token(), "demo").unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(
+            transport.requests()[0].url,
+            "https://api.example.com/namespace?productkey=demo"
+        );
+    }
+
+    #[test]
+    fn hostname_defaults_to_https() {
+        let base = BaseUrl::parse("api.example.com").unwrap();
+        assert_eq!(base.namespace_url(), "https://api.example.com/namespace");
+    }
+
+    #[test]
+    fn url_with_base_path() {
+        let base = BaseUrl::parse("http://127.0.0.1:8080/platform/v2/").unwrap();
+        assert_eq!(
+            base.namespace_url(),
+            "http://127.0.0.1:8080/platform/v2/namespace"
+        );
+        assert_eq!(
+            base.namespace_item_url("demo-test"),
+            "http://127.0.0.1:8080/platform/v2/namespace/demo-test"
+        );
+    }
+
+    #[test]
+    fn versioned_endpoints() {
+        let base = BaseUrl::parse("https://api.example.com/platform").unwrap();
+        let v2 = base.with_version("v2");
+        assert_eq!(
+            v2.namespace_url(),
+            "https://api.example.com/platform/v2/namespace"
+        );
+        assert_eq!(
+            v2.endpoint_url("version"),
+            "https://api.example.com/platform/version"
+        );
+    }
+
+    #[test]
+    fn unsupported_scheme() {
+        assert!(BaseUrl::parse("ftp://api.example.com").is_err());
+        assert!(BaseUrl::parse("https://").is_err());
+    }
+}
@@ -1,9 +1,12 @@
-use reqwest::blocking::Client;
 use std::env;
 use urlencoding::decode;
 
+use crate::transport::{Body, Method, Request, Transport};
 use crate::types::{Error, OAuthCred, Token};
 
+const AUTHORITY_ENV_VAR: &str = "PLATFORM_API_AUTHORITY";
+const DEFAULT_AUTHORITY: &str = "https://login.microsoftonline.com";
+
 fn get_env_var(name: &str) -> Result<String, Error> {
     env::var(name).map_err(|e| {
         Error::Environment(format!("Could not get '{}' from environment: {}", name, e))
@@ -13,32 +16,51 @@ fn get_env_var(name: &str) -> Result<String, Error> {
 fn get_oauth_creds_from_env() -> Result<OAuthCred, Error> {
     let mut scope = get_env_var("SCOPE")?;
     // hack to deal with already urlencoded data so that it isn't encoded twice...
-    if (&scope).contains("%3A%2F%2F") {
+    if scope.contains("%3A%2F%2F") {
         scope = decode(&scope).map_err(|e| Error::Unknown(e.to_string()))?;
     }
     Ok(OAuthCred::new(
         scope,
         get_env_var("CLIENT_ID")?,
-        get_env_var("CLIENT_SECRET")?,
+        get_env_var("CLIENT_SECRET")?.into(),
     ))
 }
 
-pub fn get_bearer_token(client: &Client, tenant: &str) -> Result<Token, Error> {
+// stands in for the credentials when replaying fixtures, which never contain
+// the real ones
+fn placeholder_creds() -> OAuthCred {
+    OAuthCred::new("replay".to_string(), "replay".to_string(), "replay".into())
+}
+
+pub fn get_bearer_token(transport: &dyn Transport, tenant: &str) -> Result<Token, Error> {
+    // overridable so that a stand-in such as `mock-server` can issue tokens
+    let authority = env::var(AUTHORITY_ENV_VAR).unwrap_or_else(|_| DEFAULT_AUTHORITY.to_string());
+    let creds = if transport.is_replay() {
+        placeholder_creds()
+    } else {
+        get_oauth_creds_from_env()?
+    };
+    request_token(transport, &authority, tenant, &creds)
+}
+
+pub fn request_token(
+    transport: &dyn Transport,
+    authority: &str,
+    tenant: &str,
+    creds: &OAuthCred,
+) -> Result<Token, Error> {
     let url = format!(
-        "https://login.microsoftonline.com/{}/oauth2/v2.0/token",
+        "{}/{}/oauth2/v2.0/token",
+        authority.trim_end_matches('/'),
         tenant
     );
-    let res = client
-        .post(&url)
-        .form(&get_oauth_creds_from_env()?)
-        .send()
+    let form = serde_urlencoded::to_string(creds)
+        .map_err(|e| Error::Unknown(format!("Error encoding OAuth request: {}", e)))?;
+    let res = transport
+        .send(Request::new(Method::Post, url).body(Body::Form(form.into())))
         .map_err(|e| Error::Unknown(format!("Error from OAuth request: {}", e)))?;
-    let s = res.status();
-    let t = res
-        .text()
-        .map_err(|e| Error::Unknown(format!("Error obtaining body of OAuth response: {}", e)))?;
-    if s.is_success() {
-        let token: Token = serde_json::from_str(&t)
+    if res.is_success() {
+        let token: Token = serde_json::from_str(&res.body)
             .map_err(|e| Error::Unknown(format!("Error decoding OAuth API Response: {}", e)))?;
         if token.get_type() != "Bearer" {
             Err(Error::Unknown(format!(
@@ -50,6 +72,121 @@ pub fn get_bearer_token(client: &Client, tenant: &str) -> Result<Token, Error> {
         }
     } else {
         // panic!("Received a {} status code from the oauth api");
-        Err(Error::OAuth(s.as_u16(), t))
+        Err(Error::OAuth(res.status, res.body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secret::Secret;
+    use crate::transport::fake::FakeTransport;
+    use crate::transport::TransportError;
+
+    fn creds() -> OAuthCred {
+        OAuthCred::new(
+            "api://platform/.default".to_string(),
+            "client".to_string(),
+            "shh".into(),
+        )
+    }
+
+    #[test]
+    fn token_success() {
+        let transport = FakeTransport::new().respond(
+            200,
+            r#"{"token_type": "Bearer", "access_token": "abc", "expires_in": 3599}"#,
+        );
+        let token = request_token(&transport, DEFAULT_AUTHORITY, "tenant-id", &creds()).unwrap();
+        assert_eq!(Secret::from(&token).expose(), "abc");
+        assert!(!format!("{:?}", token).contains("abc"));
+        let reqs = transport.requests();
+        assert_eq!(
+            reqs[0].url,
+            "https://login.microsoftonline.com/tenant-id/oauth2/v2.0/token"
+        );
+        match &reqs[0].body {
+            Some(Body::Form(form)) => {
+                assert!(form.expose().contains("grant_type=client_credentials"));
+                assert!(form
+                    .expose()
+                    .contains("scope=api%3A%2F%2Fplatform%2F.default"));
+                assert!(!format!("{:?}", reqs[0]).contains("shh"));
+            }
+            other => panic!("unexpected body {:?}", other),
+        }
+    }
+
+    #[test]
+    fn token_wrong_type() {
+        let transport =
+            FakeTransport::new().respond(200, r#"{"token_type": "MAC", "access_token": "abc"}"#);
+        match request_token(&transport, DEFAULT_AUTHORITY, "tenant-id", &creds()) {
+            Err(Error::Unknown(msg)) => assert_eq!(msg, "Unknown token type: MAC"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn token_client_error() {
+        let transport = FakeTransport::new().respond(401, "invalid_client");
+        match request_token(&transport, DEFAULT_AUTHORITY, "tenant-id", &creds()) {
+            Err(Error::OAuth(401, body)) => assert_eq!(body, "invalid_client"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn token_server_error() {
+        let transport = FakeTransport::new().respond(500, "oops");
+        match request_token(&transport, DEFAULT_AUTHORITY, "tenant-id", &creds()) {
+            Err(Error::OAuth(500, _)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn token_timeout() {
+        let transport = FakeTransport::new().fail(TransportError::Timeout);
+        match request_token(&transport, DEFAULT_AUTHORITY, "tenant-id", &creds()) {
+            Err(Error::Unknown(msg)) => assert!(msg.contains("timed out")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn token_malformed_json() {
+        let transport = FakeTransport::new().respond(200, "<html>");
+        match request_token(&transport, DEFAULT_AUTHORITY, "tenant-id", &creds()) {
+            Err(Error::Unknown(msg)) => {
+                assert!(msg.starts_with("Error decoding OAuth API Response"))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    // fixtures are replayed without the credential env vars
+    #[test]
+    fn token_replay_uses_placeholders() {
+        struct Replay(FakeTransport);
+        impl Transport for Replay {
+            fn send(&self, req: Request) -> Result<crate::transport::Response, TransportError> {
+                self.0.send(req)
+            }
+            fn is_replay(&self) -> bool {
+                true
+            }
+        }
+        let transport = Replay(
+            FakeTransport::new().respond(200, r#"{"token_type": "Bearer", "access_token": "abc"}"#),
+        );
+        assert!(get_bearer_token(&transport, "tenant-id").is_ok());
+        match &transport.0.requests()[0].body {
+            Some(Body::Form(form)) => {
+                assert!(form.expose().contains("client_id=replay"));
+                assert!(form.expose().contains("client_secret=replay"));
+            }
+            other => panic!("unexpected body {:?}", other),
+        }
     }
 }
@@ -0,0 +1,400 @@
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::api::{self, BaseUrl};
+use crate::transport::Transport;
+use crate::types::{Error, NSDef, Token};
+
+// namespace API versions this client can speak, newest first
+const SUPPORTED_VERSIONS: &[&str] = &["v1"];
+const CACHE_TTL_HOURS: i64 = 24;
+// probes which failed are retried sooner, but not on every command
+const FAILED_CACHE_TTL_MINUTES: i64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cache {
+    Use,
+    Refresh,
+    Off,
+}
+
+impl Cache {
+    // recordings have to contain the probes and replays have to make them,
+    // so cached capabilities are never mixed with fixtures
+    pub fn select(fixtures: bool, refresh: bool) -> Self {
+        if fixtures {
+            Cache::Off
+        } else if refresh {
+            Cache::Refresh
+        } else {
+            Cache::Use
+        }
+    }
+}
+
+// what a Platform API deployment says it supports. empty or missing values
+// mean it didn't say, in which case nothing is assumed.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Capabilities {
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub api_versions: Vec<String>,
+    #[serde(default)]
+    pub namespace_fields: Option<BTreeSet<String>>,
+    // whether fields beyond namespace_fields are accepted anyway
+    #[serde(default)]
+    pub additional_fields: bool,
+}
+
+fn strings(value: Option<&Value>) -> Option<Vec<String>> {
+    value.and_then(Value::as_array).map(|items| {
+        items
+            .iter()
+            .filter_map(Value::as_str)
+            .map(String::from)
+            .collect()
+    })
+}
+
+// follow a local '#/components/schemas/...' reference
+fn resolve<'a>(doc: &'a Value, schema: &'a Value) -> &'a Value {
+    match schema.get("$ref").and_then(Value::as_str) {
+        Some(reference) => reference
+            .strip_prefix('#')
+            .and_then(|pointer| doc.pointer(pointer))
+            .unwrap_or(schema),
+        None => schema,
+    }
+}
+
+impl Capabilities {
+    // eg. {"version": "2.3.1", "api_versions": ["v1"], "namespace_fields": ["productkey", ...]}
+    fn from_version_document(doc: &Value) -> Capabilities {
+        Capabilities {
+            version: doc.get("version").and_then(Value::as_str).map(String::from),
+            api_versions: strings(doc.get("api_versions")).unwrap_or_default(),
+            namespace_fields: strings(doc.get("namespace_fields"))
+                .map(|fields| fields.into_iter().collect()),
+            additional_fields: doc
+                .get("additional_fields")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+        }
+    }
+
+    // versions come from paths such as '/v1/namespace', and fields from the
+    // request body schema for creating a namespace
+    fn from_openapi(doc: &Value) -> Capabilities {
+        let paths = doc.get("paths").and_then(Value::as_object);
+        let api_versions = paths
+            .map(|paths| {
+                paths
+                    .keys()
+                    .filter_map(|p| p.strip_prefix('/')?.strip_suffix("/namespace"))
+                    .filter(|v| !v.contains('/'))
+                    .map(String::from)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let schema = paths
+            .and_then(|paths| {
+                paths
+                    .iter()
+                    .find(|(p, _)| p.ends_with("/namespace"))
+                    .map(|(_, item)| item)
+            })
+            .and_then(|item| item.pointer("/post/requestBody/content/application~1json/schema"))
+            .map(|schema| resolve(doc, schema));
+        let namespace_fields = schema
+            .and_then(|schema| schema.get("properties"))
+            .and_then(Value::as_object)
+            .map(|props| props.keys().cloned().collect());
+        // 'true' or a schema for the extra values both accept any field
+        let additional_fields = matches!(
+            schema.and_then(|schema| schema.get("additionalProperties")),
+            Some(Value::Bool(true)) | Some(Value::Object(_))
+        );
+        Capabilities {
+            version: doc
+                .pointer("/info/version")
+                .and_then(Value::as_str)
+                .map(String::from),
+            api_versions,
+            namespace_fields,
+            additional_fields,
+        }
+    }
+
+    fn fetch(transport: &dyn Transport, base: &BaseUrl, token: &Token) -> Result<Self, Error> {
+        if let Some(doc) = api::discover(transport, base, token, "version")? {
+            return Ok(Capabilities::from_version_document(&doc));
+        }
+        if let Some(doc) = api::discover(transport, base, token, "openapi.json")? {
+            return Ok(Capabilities::from_openapi(&doc));
+        }
+        Ok(Capabilities::default())
+    }
+
+    // a failed probe never stops a command, older deployments may not
+    // support it at all. failures are cached briefly so an API which keeps
+    // failing isn't probed by every command.
+    pub fn probe(transport: &dyn Transport, base: &BaseUrl, token: &Token, cache: Cache) -> Self {
+        if cache == Cache::Use {
+            match cached(base) {
+                Some(CacheEntry { error: Some(e), .. }) => {
+                    info!("not probing {}, it failed recently: {}", base, e);
+                    return Capabilities::default();
+                }
+                Some(entry) => {
+                    info!("using cached capabilities for {}", base);
+                    return entry.capabilities;
+                }
+                None => {}
+            }
+        }
+        let (caps, error) = match Capabilities::fetch(transport, base, token) {
+            Ok(caps) => (caps, None),
+            Err(e) => {
+                warn!("could not probe capabilities of {}: {}", base, e);
+                (Capabilities::default(), Some(e.to_string()))
+            }
+        };
+        if cache != Cache::Off {
+            store(
+                base,
+                CacheEntry {
+                    fetched: Utc::now(),
+                    capabilities: caps.clone(),
+                    error,
+                },
+            );
+        }
+        caps
+    }
+
+    // the newest version both sides support, or the unversioned endpoint if
+    // the API doesn't advertise any
+    pub fn negotiate(&self, base: &BaseUrl) -> Result<BaseUrl, Error> {
+        if self.api_versions.is_empty() {
+            return Ok(base.clone());
+        }
+        match SUPPORTED_VERSIONS
+            .iter()
+            .find(|v| self.api_versions.iter().any(|a| a == *v))
+        {
+            Some(version) => {
+                info!("using API version {} of {}", version, base);
+                Ok(base.with_version(version))
+            }
+            None => Err(Error::Unknown(format!(
+                "Platform API at {} supports API versions {}, but this client only supports {}",
+                base,
+                self.api_versions.join(", "),
+                SUPPORTED_VERSIONS.join(", ")
+            ))),
+        }
+    }
+
+    pub fn unsupported_fields(&self, payload: &NSDef) -> Vec<String> {
+        let known = match &self.namespace_fields {
+            Some(known) if !self.additional_fields => known,
+            _ => return Vec::new(),
+        };
+        match serde_json::to_value(payload) {
+            Ok(Value::Object(map)) => map
+                .keys()
+                .filter(|k| !known.contains(*k))
+                .cloned()
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn check(&self, payload: &NSDef, fail: bool) -> Result<(), Error> {
+        let unsupported = self.unsupported_fields(payload);
+        if unsupported.is_empty() {
+            return Ok(());
+        }
+        let name = format!("{}-{}", payload.productkey, payload.namespace);
+        let version = self.version.as_deref().unwrap_or("unknown");
+        if fail {
+            Err(Error::UnsupportedFields(
+                name,
+                version.to_string(),
+                unsupported.join(", "),
+            ))
+        } else {
+            eprintln!(
+                "Warning: Platform API version {} doesn't declare fields sent for {}: {}",
+                version,
+                name,
+                unsupported.join(", ")
+            );
+            Ok(())
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    fetched: DateTime<Utc>,
+    capabilities: Capabilities,
+    // set when the probe failed, rather than the API not supporting it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl CacheEntry {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        let ttl = match self.error {
+            Some(_) => Duration::minutes(FAILED_CACHE_TTL_MINUTES),
+            None => Duration::hours(CACHE_TTL_HOURS),
+        };
+        now - self.fetched < ttl
+    }
+}
+
+fn cache_path() -> Option<PathBuf> {
+    let cache_dir = match env::var("XDG_CACHE_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(env::var("HOME").ok()?).join(".cache"),
+    };
+    Some(
+        cache_dir
+            .join("platformapi-namespace")
+            .join("capabilities.json"),
+    )
+}
+
+fn read_cache() -> BTreeMap<String, CacheEntry> {
+    cache_path()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn cached(base: &BaseUrl) -> Option<CacheEntry> {
+    read_cache()
+        .remove(&base.to_string())
+        .filter(|entry| entry.is_fresh(Utc::now()))
+}
+
+// caching is best-effort, like the ledger
+fn store(base: &BaseUrl, entry: CacheEntry) {
+    let path = match cache_path() {
+        Some(path) => path,
+        None => return,
+    };
+    let mut cache = read_cache();
+    cache.insert(base.to_string(), entry);
+    let written = path
+        .parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| fs::write(&path, serde_json::to_string_pretty(&cache).unwrap()));
+    if let Err(e) = written {
+        warn!("could not cache capabilities in {}: {}", path.display(), e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::NSDefBuilder;
+    use serde_json::json;
+
+    fn payload() -> NSDef {
+        let mut extra = crate::types::ExtraProps::new();
+        extra.insert("quota".to_string(), json!({"cpu": 4}));
+        NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("24h")
+            .cluster("cluster")
+            .namespace("test")
+            .extra_properties(extra)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn openapi_versions_and_fields() {
+        let doc = json!({
+            "info": {"version": "2.1.0"},
+            "paths": {
+                "/v1/namespace": {
+                    "post": {"requestBody": {"content": {"application/json": {
+                        "schema": {"$ref": "#/components/schemas/NSDef"}
+                    }}}}
+                },
+                "/v1/namespace/{name}": {}
+            },
+            "components": {"schemas": {"NSDef": {"properties": {
+                "productkey": {}, "ttl": {}, "cluster": {}, "namespace": {}
+            }}}}
+        });
+        let caps = Capabilities::from_openapi(&doc);
+        assert_eq!(caps.version.as_deref(), Some("2.1.0"));
+        assert_eq!(caps.api_versions, vec!["v1"]);
+        assert_eq!(caps.unsupported_fields(&payload()), vec!["quota"]);
+        assert!(caps.check(&payload(), true).is_err());
+        assert!(caps.check(&payload(), false).is_ok());
+    }
+
+    #[test]
+    fn openapi_additional_properties() {
+        for additional in &[json!(true), json!({"type": "string"})] {
+            let doc = json!({
+                "paths": {"/namespace": {"post": {"requestBody": {"content": {
+                    "application/json": {"schema": {
+                        "properties": {"productkey": {}, "ttl": {}, "cluster": {}, "namespace": {}},
+                        "additionalProperties": additional
+                    }}
+                }}}}}
+            });
+            let caps = Capabilities::from_openapi(&doc);
+            assert!(caps.additional_fields);
+            assert!(caps.unsupported_fields(&payload()).is_empty());
+            assert!(caps.check(&payload(), true).is_ok());
+        }
+    }
+
+    #[test]
+    fn failed_probes_expire_sooner() {
+        let now = Utc::now();
+        let entry = |error: Option<&str>| CacheEntry {
+            fetched: now - Duration::minutes(FAILED_CACHE_TTL_MINUTES + 1),
+            capabilities: Capabilities::default(),
+            error: error.map(String::from),
+        };
+        assert!(entry(None).is_fresh(now));
+        assert!(!entry(Some("500 Internal Server Error")).is_fresh(now));
+        let recent = CacheEntry {
+            fetched: now,
+            ..entry(Some("500 Internal Server Error"))
+        };
+        assert!(recent.is_fresh(now));
+    }
+
+    #[test]
+    fn negotiate_versions() {
+        let base = BaseUrl::parse("api.example.com").unwrap();
+        let legacy = Capabilities::default();
+        assert_eq!(legacy.negotiate(&base).unwrap(), base);
+        assert!(legacy.unsupported_fields(&payload()).is_empty());
+
+        let caps = Capabilities::from_version_document(&json!({"api_versions": ["v1", "v2"]}));
+        assert_eq!(
+            caps.negotiate(&base).unwrap().namespace_url(),
+            "https://api.example.com/v1/namespace"
+        );
+
+        let newer = Capabilities::from_version_document(&json!({"api_versions": ["v3"]}));
+        assert!(newer.negotiate(&base).is_err());
+    }
+}
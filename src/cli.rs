@@ -0,0 +1,647 @@
+use chrono::DateTime;
+use clap::{App, AppSettings, Arg, ArgGroup, SubCommand};
+use core::time::Duration;
+use regex::Regex;
+
+use crate::mock::Fault;
+
+// 1-24h or 1-7d
+pub const TTL_PATTERN: &str = r"^(1([hd]|[0-9]h)|2([hd]|[0-4]h)|[3-7][hd]|[89]h)$";
+
+pub fn validate_ttl(inp: String) -> Result<(), String> {
+    let re = Regex::new(TTL_PATTERN).unwrap();
+    if re.is_match(&inp) {
+        Ok(())
+    } else {
+        Err(String::from("Valid TTLs are 1-24h or 1-7d"))
+    }
+}
+
+fn validate_positive_int(inp: String) -> Result<(), String> {
+    match inp.parse::<usize>() {
+        Ok(v) if v > 0 => Ok(()),
+        _ => Err(String::from("Value must be a positive integer")),
+    }
+}
+
+// durations such as '90s', '30m', '2h' or '7d'
+pub fn parse_duration(inp: &str) -> Option<Duration> {
+    let (split, _) = inp.char_indices().last()?;
+    let (num, unit) = inp.split_at(split);
+    let num: u64 = num.parse().ok()?;
+    let multiplier = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+    num.checked_mul(multiplier).map(Duration::from_secs)
+}
+
+fn validate_duration(inp: String) -> Result<(), String> {
+    match parse_duration(&inp) {
+        Some(_) => Ok(()),
+        None => Err(String::from(
+            "Durations are a number followed by s, m, h or d (eg. 30m)",
+        )),
+    }
+}
+
+fn validate_timestamp(inp: String) -> Result<(), String> {
+    DateTime::parse_from_rfc3339(&inp)
+        .map(|_| ())
+        .map_err(|e| format!("Expected an RFC3339 timestamp: {}", e))
+}
+
+fn connection_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("hostname")
+            .long("hostname")
+            .required(false)
+            .takes_value(true)
+            .help("hostname of API, otherwise read from PLATFORM_API_HOSTNAME env var"),
+        Arg::with_name("base-url")
+            .long("base-url")
+            .required(false)
+            .takes_value(true)
+            .conflicts_with("hostname")
+            .help("URL the API is served from, eg. http://127.0.0.1:8080/platform/v2, otherwise read from PLATFORM_API_BASE_URL env var. can't be combined with --hostname, but PLATFORM_API_BASE_URL is used before PLATFORM_API_HOSTNAME and ignored when --hostname is given"),
+        Arg::with_name("cluster")
+            .long("cluster")
+            .required(false)
+            .takes_value(true)
+            .help("cluster name, otherwise read from PLATFORM_API_CLUSTER env var"),
+        Arg::with_name("tenant")
+            .long("tenant")
+            .required(false)
+            .takes_value(true)
+            .help("tenant info for auth, otherwise read from PLATFORM_API_TENANT env var"),
+        Arg::with_name("no-probe")
+            .long("no-probe")
+            .help("don't probe the API for its supported versions and fields. probing is on by default and costs up to two extra requests when nothing is cached")
+            .takes_value(false)
+            .required(false),
+        Arg::with_name("refresh-capabilities")
+            .long("refresh-capabilities")
+            .help("probe the API again rather than using capabilities cached in the last day")
+            .takes_value(false)
+            .required(false),
+        Arg::with_name("unsupported-fields")
+            .long("unsupported-fields")
+            .help("what to do when the payload has fields the API doesn't declare")
+            .possible_values(&["warn", "fail"])
+            .default_value("warn")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("config")
+            .long("config")
+            .help("config file, otherwise read from PLATFORM_API_CONFIG env var or ~/.config/platformapi-namespace/config.yaml")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("proxy")
+            .long("proxy")
+            .help("proxy URL for the OAuth and Platform APIs, otherwise read from PLATFORM_API_PROXY env var")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("no-proxy")
+            .long("no-proxy")
+            .help("comma separated hosts and domains to reach without --proxy, otherwise read from PLATFORM_API_NO_PROXY env var. may be repeated")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .required(false),
+        Arg::with_name("ca-cert")
+            .long("ca-cert")
+            .help("extra PEM CA certificate bundle to trust, otherwise read from PLATFORM_API_CA_CERT env var. may be repeated")
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1)
+            .required(false),
+        Arg::with_name("client-cert")
+            .long("client-cert")
+            .help("PKCS#12 (.p12/.pfx) client certificate for mutual TLS, otherwise read from PLATFORM_API_CLIENT_CERT env var. its password is read from PLATFORM_API_CLIENT_CERT_PASSWORD. PEM certificates and keys aren't supported, convert them with 'openssl pkcs12 -export'")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("connect-timeout")
+            .long("connect-timeout")
+            .help("seconds to wait for a connection, otherwise read from PLATFORM_API_CONNECT_TIMEOUT_SECS env var")
+            .validator(validate_positive_int)
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("read-timeout")
+            .long("read-timeout")
+            .help("seconds to wait for the response to start, or for more of it to arrive, otherwise read from PLATFORM_API_READ_TIMEOUT_SECS env var")
+            .validator(validate_positive_int)
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("timeout")
+            .long("timeout")
+            .help("seconds to wait for each request to complete, otherwise read from PLATFORM_API_TIMEOUT_SECS env var. defaults to 90")
+            .validator(validate_positive_int)
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("record")
+            .long("record")
+            .help("write every OAuth and Platform API exchange, with secrets redacted, to fixture files in this directory")
+            .takes_value(true)
+            .conflicts_with("replay")
+            .required(false),
+        Arg::with_name("replay")
+            .long("replay")
+            .help("serve OAuth and Platform API responses from fixture files written by --record instead of the network")
+            .takes_value(true)
+            .required(false),
+    ]
+}
+
+fn interpolation_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("interpolate")
+            .long("interpolate")
+            .help("substitute ${VAR}, ${VAR-default} and ${VAR:-default} from the environment in files read for spec, manifest and extra data")
+            .takes_value(false)
+            .required(false),
+        Arg::with_name("strict-interpolation")
+            .long("strict-interpolation")
+            .help("as --interpolate, but fail on variables which are unset and have no default")
+            .takes_value(false)
+            .required(false),
+    ]
+}
+
+fn allow_override_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("allow-override")
+        .long("allow-override")
+        .help("allow extra data to replace core fields such as namespace, ttl or cluster")
+        .takes_value(false)
+        .required(false)
+}
+
+fn dry_run_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("debug")
+        .short("d")
+        .long("dry-run")
+        .takes_value(false)
+        .required(false)
+}
+
+fn output_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("output")
+        .long("output")
+        .short("o")
+        .help("how to print the API response. json and yaml include every field it returned")
+        .possible_values(&["human", "json", "yaml"])
+        .default_value("human")
+        .takes_value(true)
+        .required(false)
+}
+
+fn concurrency_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("concurrency")
+        .long("concurrency")
+        .short("j")
+        .help("maximum number of namespaces to submit at once")
+        .validator(validate_positive_int)
+        .default_value("4")
+        .takes_value(true)
+        .required(false)
+}
+
+fn validate_regex(inp: String) -> Result<(), String> {
+    Regex::new(&inp).map(|_| ()).map_err(|e| e.to_string())
+}
+
+// args describing the contents of a namespace payload
+fn payload_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = vec![
+        Arg::with_name("ttl")
+            .long("ttl")
+            .help("ttl for namespace. valid values are 1-24h or 1-7d")
+            .validator(validate_ttl)
+            .default_value("24h")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("labels")
+            .short("l")
+            .long("labels")
+            .required(false)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("annotations")
+            .short("a")
+            .long("annotation")
+            .required(false)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("provenance")
+            .long("provenance")
+            .help("add labels and annotations describing the git checkout and CI job creating the namespace")
+            .takes_value(false)
+            .required(false),
+        Arg::with_name("svcac")
+            .long("vault-service-account")
+            .help("add an additional service account for vault access")
+            .required(false)
+            .takes_value(true)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("svcac-raw")
+            .long("vault-service-account-raw")
+            .help("service accounts for vault access. comma-separated raw list of values.")
+            .required(false)
+            .takes_value(true)
+            .multiple(false)
+            .conflicts_with("svcac")
+            .number_of_values(1),
+        Arg::with_name("extra-props")
+            .long("extra-data")
+            .help("provide extra params to api by reading in yaml/json. value prefixed with '@' is treated as a filename. may be repeated, later values are deep-merged over earlier ones and null values remove keys.")
+            .takes_value(true)
+            .required(false)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("set")
+            .long("set")
+            .help("set an extra param, eg. quota.cpu=4. the value is parsed as yaml. applied in order with --extra-data")
+            .takes_value(true)
+            .required(false)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("set-json")
+            .long("set-json")
+            .help("set an extra param to a json value, eg. 'quota={\"cpu\": 4}'")
+            .takes_value(true)
+            .required(false)
+            .multiple(true)
+            .number_of_values(1),
+        Arg::with_name("unset")
+            .long("unset")
+            .help("remove an extra param, eg. quota.memory")
+            .takes_value(true)
+            .required(false)
+            .multiple(true)
+            .number_of_values(1),
+        allow_override_arg(),
+    ];
+    args.extend(interpolation_args());
+    args
+}
+
+// args naming a single namespace along with its payload, shared by create,
+// exec and plan
+fn namespace_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = vec![
+        Arg::with_name("strip-prefix")
+            .long("strip-prefix")
+            .help("strip prefix from namespace name if it is already prepended")
+            .short("s")
+            .takes_value(false)
+            .required(false),
+        Arg::with_name("manifest")
+            .long("metadata-from-manifest")
+            .help("take name, labels and annotations from a kubernetes manifest. value prefixed with '@' is treated as a filename, '@-' reads from stdin.")
+            .required_if("name", "-")
+            .takes_value(true)
+            .multiple(false)
+            .number_of_values(1),
+        Arg::with_name("manifest-selector")
+            .long("manifest-selector")
+            .help("'kind/name' of the document to use when the manifest contains several. defaults to the Namespace document")
+            .requires("manifest")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("productkey")
+            .required(true)
+            .index(1)
+            .help("product key, prepended to namespace name"),
+        Arg::with_name("name")
+            .required(true)
+            .index(2)
+            .help("namespace name, appended as suffix to product key"),
+    ];
+    args.extend(payload_args());
+    args
+}
+
+fn wait_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("wait")
+            .long("wait")
+            .help("wait for the namespace to become Active after creation")
+            .takes_value(false)
+            .required(false),
+        Arg::with_name("wait-timeout")
+            .long("wait-timeout")
+            .help("seconds to wait for the namespace to become Active")
+            .validator(validate_positive_int)
+            .default_value("300")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("wait-via")
+            .long("wait-via")
+            .help("where to poll the namespace status from")
+            .possible_values(&["platform-api", "kubernetes"])
+            .default_value("platform-api")
+            .takes_value(true)
+            .required(false),
+    ]
+}
+
+fn kubeconfig_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("kubeconfig")
+            .long("kubeconfig")
+            .help("kubeconfig file, otherwise read from KUBECONFIG env var or ~/.kube/config")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("context")
+            .long("context")
+            .help("kubeconfig context to use, otherwise the current context")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("update-kubeconfig")
+            .long("update-kubeconfig")
+            .help("after creation, add a context named after the namespace based on --context ('add-context'), also make it the current context ('use-context'), or set the namespace of --context itself ('set-namespace')")
+            .possible_values(&["add-context", "use-context", "set-namespace"])
+            .takes_value(true)
+            .required(false),
+    ]
+}
+
+fn validate_failure(inp: String) -> Result<(), String> {
+    Fault::parse_failure(&inp).map(|_| ())
+}
+
+fn validate_latency(inp: String) -> Result<(), String> {
+    Fault::parse_latency(&inp).map(|_| ())
+}
+
+pub fn app<'a, 'b>() -> App<'a, 'b> {
+    App::new("Platform API Namespace Client")
+        .version(env!("CARGO_PKG_VERSION"))
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("create")
+                .about("Create Dynamic Namespace")
+                .args(&namespace_args())
+                .args(&wait_args())
+                .args(&kubeconfig_args())
+                .arg(output_arg())
+                .arg(dry_run_arg())
+                .args(&connection_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("exec")
+                .about("Create a Dynamic Namespace, run a command against it, then delete it")
+                .args(&namespace_args())
+                .arg(
+                    Arg::with_name("keep-on-failure")
+                        .long("keep-on-failure")
+                        .help("don't delete the namespace if the command exits non-zero")
+                        .takes_value(false)
+                        .required(false),
+                )
+                .args(&connection_args())
+                .arg(
+                    Arg::with_name("command")
+                        .required(true)
+                        .multiple(true)
+                        .last(true)
+                        .help("command to run, given after '--'. NAMESPACE and NAMESPACE_EXPIRY are set in its environment"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("pr")
+                .about("Create or delete the Dynamic Namespace for a GitHub pull request event")
+                .args(&payload_args())
+                .arg(
+                    Arg::with_name("event-path")
+                        .long("event-path")
+                        .help("GitHub event payload, otherwise read from GITHUB_EVENT_PATH env var")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("suffix-from")
+                        .long("suffix-from")
+                        .help("derive the namespace name from the pull request number ('pr-42') or its branch")
+                        .possible_values(&["number", "branch"])
+                        .default_value("number")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(output_arg())
+                .arg(dry_run_arg())
+                .args(&connection_args())
+                .arg(
+                    Arg::with_name("productkey")
+                        .required(true)
+                        .index(1)
+                        .help("product key, prepended to namespace name"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("plan")
+                .about("Show how a create would change the live namespace")
+                .args(&namespace_args())
+                .args(&connection_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("keepalive")
+                .about("Keep renewing the Dynamic Namespaces described in a spec file until a deadline")
+                .arg(
+                    Arg::with_name("renew-before")
+                        .long("renew-before")
+                        .help("how long before expiry to renew each namespace, eg. 2h")
+                        .validator(validate_duration)
+                        .default_value("1h")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("until")
+                        .long("until")
+                        .help("RFC3339 timestamp after which namespaces are no longer renewed")
+                        .validator(validate_timestamp)
+                        .takes_value(true)
+                        .required(true),
+                )
+                .args(&interpolation_args())
+                .arg(allow_override_arg())
+                .args(&connection_args())
+                .arg(
+                    Arg::with_name("spec")
+                        .required(true)
+                        .index(1)
+                        .help("yaml file of namespace specs, as used by apply. '-' reads from stdin"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("gc")
+                .about("Delete Dynamic Namespaces matching a selector")
+                .arg(
+                    Arg::with_name("productkey")
+                        .long("productkey")
+                        .help("product key to list namespaces for")
+                        .takes_value(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("selector")
+                        .long("selector")
+                        .help("label selector, eg. 'pr-state=closed,team!=core'. may be repeated")
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("older-than")
+                        .long("older-than")
+                        .help("only namespaces created longer ago than this, eg. 2d")
+                        .validator(validate_duration)
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("name-regex")
+                        .long("name-regex")
+                        .help("only namespaces whose name matches this regex")
+                        .validator(validate_regex)
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("all")
+                        .long("all")
+                        .help("delete every namespace of the product key")
+                        .conflicts_with_all(&["selector", "older-than", "name-regex"])
+                        .takes_value(false)
+                        .required(false),
+                )
+                // deleting everything has to be asked for explicitly
+                .group(
+                    ArgGroup::with_name("filter")
+                        .args(&["selector", "older-than", "name-regex", "all"])
+                        .multiple(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::with_name("yes")
+                        .long("yes")
+                        .short("y")
+                        .help("don't ask for confirmation before deleting")
+                        .takes_value(false)
+                        .required(false),
+                )
+                .arg(concurrency_arg())
+                .arg(dry_run_arg())
+                .args(&connection_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Show namespaces recorded in the local ledger")
+                .arg(
+                    Arg::with_name("active")
+                        .long("active")
+                        .help("only show namespaces which haven't been deleted or expired")
+                        .takes_value(false)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("mock-server")
+                .about("Serve an in-memory stand-in for the OAuth and Platform APIs over plain HTTP")
+                .arg(
+                    Arg::with_name("listen")
+                        .long("listen")
+                        .help("address to listen on, port 0 picks a free port")
+                        .default_value("127.0.0.1:8080")
+                        .takes_value(true)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("fail")
+                        .long("fail")
+                        .help("respond to a route (token, create, get, list, delete) with a status code, optionally only the next N times, eg. create=503x2. may be repeated")
+                        .validator(validate_failure)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("latency")
+                        .long("latency")
+                        .help("delay responses to a route by some milliseconds, eg. get=1500. may be repeated")
+                        .validator(validate_latency)
+                        .takes_value(true)
+                        .multiple(true)
+                        .number_of_values(1)
+                        .required(false),
+                )
+                .arg(
+                    Arg::with_name("dump")
+                        .long("dump")
+                        .help("append every received request to this file as JSON lines")
+                        .takes_value(true)
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("apply")
+                .about("Create or update Dynamic Namespaces described in a spec file")
+                .arg(concurrency_arg())
+                .arg(dry_run_arg())
+                .args(&interpolation_args())
+                .arg(allow_override_arg())
+                .args(&connection_args())
+                .arg(
+                    Arg::with_name("spec")
+                        .required(true)
+                        .index(1)
+                        .help("yaml file of namespace specs, may contain multiple documents or lists. '-' reads from stdin"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("schema")
+                .about("Print the JSON Schema of spec files, extra data or the API payload, eg. for editor integration")
+                .arg(
+                    Arg::with_name("kind")
+                        .index(1)
+                        .possible_values(&["spec", "extra-data", "payload"])
+                        .default_value("spec")
+                        .help("which schema to print"),
+                ),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("30m"), Some(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_duration("2h"), Some(Duration::from_secs(2 * 60 * 60)));
+        assert_eq!(
+            parse_duration("7d"),
+            Some(Duration::from_secs(7 * 24 * 60 * 60))
+        );
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("m"), None);
+        assert_eq!(parse_duration("5w"), None);
+    }
+
+    #[test]
+    fn non_ascii_suffix() {
+        assert_eq!(parse_duration("5é"), None);
+        assert_eq!(parse_duration("5µ"), None);
+        assert_eq!(parse_duration("é"), None);
+        assert!(validate_duration("5µ".to_string()).is_err());
+    }
+}
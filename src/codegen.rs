@@ -0,0 +1,338 @@
+// generates src/generated.rs from the object schemas of the vendored
+// OpenAPI document. it only runs as a test, which fails when the checked-in
+// code is stale and rewrites it when UPDATE_GENERATED is set.
+use serde_yaml::Value;
+use std::fmt::Write;
+
+const SPEC_PATH: &str = "openapi/platform-api.yaml";
+const GENERATED_PATH: &str = "src/generated.rs";
+const MAX_WIDTH: usize = 100;
+const KEYWORDS: &[&str] = &[
+    "as", "async", "await", "const", "crate", "enum", "fn", "impl", "in", "let", "loop", "match",
+    "mod", "move", "mut", "pub", "ref", "self", "static", "struct", "super", "trait", "type",
+    "use", "where",
+];
+
+struct Field {
+    ident: String,
+    name: String,
+    type_: String,
+    required: bool,
+    description: Option<String>,
+}
+
+struct Struct {
+    name: String,
+    description: Option<String>,
+    fields: Vec<Field>,
+    additional_properties: bool,
+    request_body: bool,
+}
+
+fn screaming_snake(name: &str) -> String {
+    let mut out = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            out.push('_');
+        }
+        out.push(c.to_ascii_uppercase());
+    }
+    out
+}
+
+fn ident(name: &str) -> String {
+    let ident: String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    if KEYWORDS.contains(&ident.as_str()) {
+        format!("{}_", ident)
+    } else {
+        ident
+    }
+}
+
+fn schema_name(reference: &str) -> Result<&str, String> {
+    reference
+        .strip_prefix("#/components/schemas/")
+        .ok_or_else(|| format!("unsupported reference '{}'", reference))
+}
+
+fn rust_type(schemas: &Value, schema: &Value) -> Result<String, String> {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let name = schema_name(reference)?;
+        let target = schemas
+            .get(name)
+            .ok_or_else(|| format!("reference to unknown schema '{}'", name))?;
+        return Ok(match target.get("x-rust-type").and_then(Value::as_str) {
+            Some(type_) => type_.to_string(),
+            None => name.to_string(),
+        });
+    }
+    if let Some(type_) = schema.get("x-rust-type").and_then(Value::as_str) {
+        return Ok(type_.to_string());
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some("string") => Ok("String".to_string()),
+        Some("integer") => Ok("i64".to_string()),
+        Some("number") => Ok("f64".to_string()),
+        Some("boolean") => Ok("bool".to_string()),
+        Some("array") => match schema.get("items") {
+            Some(items) => Ok(format!("Vec<{}>", rust_type(schemas, items)?)),
+            None => Ok("Vec<Value>".to_string()),
+        },
+        Some("object") | None => Ok("Value".to_string()),
+        Some(other) => Err(format!("unsupported schema type '{}'", other)),
+    }
+}
+
+fn description(schema: &Value) -> Option<String> {
+    schema
+        .get("description")
+        .and_then(Value::as_str)
+        .map(String::from)
+}
+
+// names of the schemas sent as request bodies
+fn request_bodies(spec: &Value) -> Vec<String> {
+    let mut out = Vec::new();
+    let paths = spec.get("paths").and_then(Value::as_mapping);
+    for item in paths.into_iter().flat_map(|p| p.iter().map(|(_, v)| v)) {
+        for op in item
+            .as_mapping()
+            .into_iter()
+            .flat_map(|m| m.iter().map(|(_, v)| v))
+        {
+            let content = op
+                .get("requestBody")
+                .and_then(|b| b.get("content"))
+                .and_then(Value::as_mapping);
+            for media in content.into_iter().flat_map(|c| c.iter().map(|(_, v)| v)) {
+                let reference = media
+                    .get("schema")
+                    .and_then(|s| s.get("$ref"))
+                    .and_then(Value::as_str);
+                if let Some(name) = reference.and_then(|r| schema_name(r).ok()) {
+                    out.push(name.to_string());
+                }
+            }
+        }
+    }
+    out
+}
+
+// object schemas become structs. schemas naming their own rust type, and
+// anything which isn't an object with properties, are only referenced.
+// request bodies also get a const listing their fields.
+fn structs(spec: &Value) -> Result<Vec<Struct>, String> {
+    let schemas = spec
+        .get("components")
+        .and_then(|c| c.get("schemas"))
+        .ok_or("no components.schemas in spec")?;
+    let mapping = schemas
+        .as_mapping()
+        .ok_or("components.schemas isn't a map")?;
+    let request_bodies = request_bodies(spec);
+    let mut out = Vec::new();
+    for (name, schema) in mapping {
+        let name = name.as_str().ok_or("schema names must be strings")?;
+        let properties = match schema.get("properties").and_then(Value::as_mapping) {
+            Some(properties) if schema.get("x-rust-type").is_none() => properties,
+            _ => continue,
+        };
+        let required: Vec<&str> = schema
+            .get("required")
+            .and_then(Value::as_sequence)
+            .map(|r| r.iter().filter_map(Value::as_str).collect())
+            .unwrap_or_default();
+        let mut fields = Vec::new();
+        for (field, prop) in properties {
+            let field = field.as_str().ok_or("property names must be strings")?;
+            let type_ =
+                rust_type(schemas, prop).map_err(|e| format!("{}.{}: {}", name, field, e))?;
+            // fall back to the referenced schema's description
+            let description = description(prop).or_else(|| {
+                let reference = prop.get("$ref").and_then(Value::as_str)?;
+                description(schemas.get(schema_name(reference).ok()?)?)
+            });
+            fields.push(Field {
+                ident: ident(field),
+                name: field.to_string(),
+                type_,
+                required: required.contains(&field),
+                description,
+            });
+        }
+        out.push(Struct {
+            name: name.to_string(),
+            description: description(schema),
+            fields,
+            additional_properties: schema
+                .get("additionalProperties")
+                .and_then(Value::as_bool)
+                .unwrap_or(false),
+            request_body: request_bodies.iter().any(|b| b == name),
+        });
+    }
+    Ok(out)
+}
+
+fn fields_const(out: &mut String, s: &Struct) {
+    let names: Vec<String> = s.fields.iter().map(|f| format!("{:?}", f.name)).collect();
+    let head = format!(
+        "pub const {}_FIELDS: &[&str] = &[",
+        screaming_snake(&s.name)
+    );
+    let line = format!("{}{}];", head, names.join(", "));
+    if line.len() <= MAX_WIDTH {
+        writeln!(out, "{}", line).unwrap();
+    } else {
+        writeln!(out, "{}", head).unwrap();
+        for name in names {
+            writeln!(out, "    {},", name).unwrap();
+        }
+        writeln!(out, "];").unwrap();
+    }
+}
+
+pub fn generate(spec: &str) -> Result<String, String> {
+    let spec: Value = serde_yaml::from_str(spec).map_err(|e| e.to_string())?;
+    let structs = structs(&spec)?;
+    let uses_value = structs
+        .iter()
+        .any(|s| s.additional_properties || s.fields.iter().any(|f| f.type_.contains("Value")));
+    let uses_map = structs.iter().any(|s| s.additional_properties);
+
+    let mut out = String::new();
+    writeln!(
+        out,
+        "// @generated from {} by src/codegen.rs, do not edit.\n\
+         // regenerate with `UPDATE_GENERATED=1 cargo test generated`\n",
+        SPEC_PATH
+    )
+    .unwrap();
+    writeln!(out, "use serde::{{Deserialize, Serialize}};").unwrap();
+    if uses_value {
+        writeln!(out, "use serde_json::Value;").unwrap();
+    }
+    if uses_map {
+        writeln!(out, "use std::collections::BTreeMap;").unwrap();
+    }
+    for s in &structs {
+        writeln!(out).unwrap();
+        if let Some(description) = &s.description {
+            writeln!(out, "/// {}", description).unwrap();
+        }
+        writeln!(
+            out,
+            "#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]\npub struct {} {{",
+            s.name
+        )
+        .unwrap();
+        for f in &s.fields {
+            if let Some(description) = &f.description {
+                writeln!(out, "    /// {}", description).unwrap();
+            }
+            let mut attrs = Vec::new();
+            if f.ident != f.name {
+                attrs.push(format!("rename = {:?}", f.name));
+            }
+            if !f.required {
+                attrs.push("default, skip_serializing_if = \"Option::is_none\"".to_string());
+            }
+            if !attrs.is_empty() {
+                writeln!(out, "    #[serde({})]", attrs.join(", ")).unwrap();
+            }
+            if f.required {
+                writeln!(out, "    pub {}: {},", f.ident, f.type_).unwrap();
+            } else {
+                writeln!(out, "    pub {}: Option<{}>,", f.ident, f.type_).unwrap();
+            }
+        }
+        if s.additional_properties {
+            writeln!(out, "    /// Properties beyond those listed in the schema.").unwrap();
+            writeln!(out, "    #[serde(flatten)]").unwrap();
+            writeln!(
+                out,
+                "    pub additional_properties: BTreeMap<String, Value>,"
+            )
+            .unwrap();
+        }
+        writeln!(out, "}}").unwrap();
+        if s.request_body {
+            writeln!(out).unwrap();
+            fields_const(&mut out, s);
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generated::{NamespaceRequest, VaultConfig};
+    use crate::types::{ExtraProps, NSDefBuilder, VaultServiceAccounts, RESERVED_FIELDS};
+    use serde_json::json;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn generated_code_matches_spec() {
+        let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let spec = fs::read_to_string(root.join(SPEC_PATH)).unwrap();
+        let expected = generate(&spec).unwrap();
+        let path = root.join(GENERATED_PATH);
+        if env::var_os("UPDATE_GENERATED").is_some() {
+            fs::write(&path, expected).unwrap();
+            return;
+        }
+        let actual = fs::read_to_string(&path).unwrap_or_default();
+        assert!(
+            actual == expected,
+            "{} is out of date with {}, regenerate it with `UPDATE_GENERATED=1 cargo test generated`",
+            GENERATED_PATH,
+            SPEC_PATH
+        );
+    }
+
+    // without overrides, the payload is exactly the generated request
+    #[test]
+    fn nsdef_payload_matches_generated_request() {
+        let mut extra = ExtraProps::new();
+        extra.insert("quota".to_string(), json!({"cpu": 4}));
+        let mut accounts = VaultServiceAccounts::new();
+        accounts.extend(vec!["deployer".to_string()]);
+        let payload = NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("24h")
+            .cluster("cluster")
+            .namespace("test")
+            .labels(klap::labels_from_str_either("team=platform").unwrap())
+            .annotations(vec![klap::annotation_from_str("owner=me").unwrap()])
+            .vault_service_accounts(accounts)
+            .extra_properties(extra)
+            .build()
+            .unwrap();
+        let request: NamespaceRequest =
+            serde_json::from_value(serde_json::to_value(&payload).unwrap()).unwrap();
+        assert_eq!(request, payload.to_request());
+        assert_eq!(request.productkey, payload.productkey);
+        assert_eq!(request.namespace, payload.namespace);
+        assert_eq!(request.labels, Some(payload.labels.clone()));
+        assert_eq!(request.annotations, Some(payload.annotations.clone()));
+        assert_eq!(
+            request.vault_config,
+            Some(VaultConfig {
+                service_account_name: "default,deployer".to_string()
+            })
+        );
+        assert_eq!(
+            request.additional_properties.keys().collect::<Vec<_>>(),
+            vec!["quota"]
+        );
+        for field in RESERVED_FIELDS {
+            assert!(!request.additional_properties.contains_key(*field));
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use clap::ArgMatches;
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::secret::Secret;
+use crate::types::Error;
+
+const CONFIG_ENV_VAR: &str = "PLATFORM_API_CONFIG";
+const PROXY_ENV_VAR: &str = "PLATFORM_API_PROXY";
+const NO_PROXY_ENV_VAR: &str = "PLATFORM_API_NO_PROXY";
+const CA_CERT_ENV_VAR: &str = "PLATFORM_API_CA_CERT";
+const CLIENT_CERT_ENV_VAR: &str = "PLATFORM_API_CLIENT_CERT";
+const CLIENT_CERT_PASSWORD_ENV_VAR: &str = "PLATFORM_API_CLIENT_CERT_PASSWORD";
+const CONNECT_TIMEOUT_ENV_VAR: &str = "PLATFORM_API_CONNECT_TIMEOUT_SECS";
+const READ_TIMEOUT_ENV_VAR: &str = "PLATFORM_API_READ_TIMEOUT_SECS";
+const TIMEOUT_ENV_VAR: &str = "PLATFORM_API_TIMEOUT_SECS";
+const REDACT_KEYS_ENV_VAR: &str = "PLATFORM_API_REDACT_KEYS";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub http: HttpConfig,
+    // keys hidden in logged payloads, replacing the defaults
+    #[serde(default)]
+    pub redact_keys: Option<Vec<String>>,
+}
+
+// settings for the client used to call both the OAuth and Platform APIs
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HttpConfig {
+    #[serde(default)]
+    pub proxy: Option<String>,
+    #[serde(default)]
+    pub no_proxy: Vec<String>,
+    #[serde(default)]
+    pub ca_certs: Vec<PathBuf>,
+    #[serde(default)]
+    pub client_cert: Option<PathBuf>,
+    #[serde(default)]
+    pub client_cert_password: Option<Secret>,
+    #[serde(default)]
+    pub connect_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub read_timeout_secs: Option<u64>,
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    let config_dir = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => PathBuf::from(env::var("HOME").ok()?).join(".config"),
+    };
+    Some(config_dir.join("platformapi-namespace").join("config.yaml"))
+}
+
+impl Config {
+    // an explicitly given config file must exist, the default one is optional
+    pub fn load(path: Option<&str>) -> Result<Config, Error> {
+        let path = match path
+            .map(String::from)
+            .or_else(|| env::var(CONFIG_ENV_VAR).ok())
+        {
+            Some(path) => PathBuf::from(path),
+            None => match default_config_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let contents =
+            fs::read_to_string(&path).map_err(|e| Error::Config(path.clone(), e.to_string()))?;
+        serde_yaml::from_str(&contents).map_err(|e| Error::Config(path, e.to_string()))
+    }
+}
+
+fn split_list(inp: &str) -> Vec<String> {
+    inp.split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn parse_secs_env(name: &str, inp: &str) -> Result<u64, Error> {
+    inp.parse().map_err(|_| {
+        Error::Environment(format!(
+            "Could not parse {} '{}' as a whole number of seconds",
+            name, inp
+        ))
+    })
+}
+
+impl Config {
+    // options take precedence over env vars, which take precedence over the
+    // config file
+    pub fn from_matches(matches: &ArgMatches<'_>) -> Result<Config, Error> {
+        let mut config = Config::load(matches.value_of("config"))?;
+        if let Ok(keys) = env::var(REDACT_KEYS_ENV_VAR) {
+            config.redact_keys = Some(split_list(&keys));
+        }
+        config.http.apply_overrides(matches)?;
+        Ok(config)
+    }
+}
+
+impl HttpConfig {
+    fn apply_overrides(&mut self, matches: &ArgMatches<'_>) -> Result<(), Error> {
+        let http = self;
+        if let Ok(proxy) = env::var(PROXY_ENV_VAR) {
+            http.proxy = Some(proxy);
+        }
+        if let Ok(no_proxy) = env::var(NO_PROXY_ENV_VAR) {
+            http.no_proxy = split_list(&no_proxy);
+        }
+        if let Ok(certs) = env::var(CA_CERT_ENV_VAR) {
+            http.ca_certs = split_list(&certs).into_iter().map(PathBuf::from).collect();
+        }
+        if let Ok(cert) = env::var(CLIENT_CERT_ENV_VAR) {
+            http.client_cert = Some(PathBuf::from(cert));
+        }
+        if let Ok(password) = env::var(CLIENT_CERT_PASSWORD_ENV_VAR) {
+            http.client_cert_password = Some(password.into());
+        }
+        if let Ok(secs) = env::var(CONNECT_TIMEOUT_ENV_VAR) {
+            http.connect_timeout_secs = Some(parse_secs_env(CONNECT_TIMEOUT_ENV_VAR, &secs)?);
+        }
+        if let Ok(secs) = env::var(READ_TIMEOUT_ENV_VAR) {
+            http.read_timeout_secs = Some(parse_secs_env(READ_TIMEOUT_ENV_VAR, &secs)?);
+        }
+        if let Ok(secs) = env::var(TIMEOUT_ENV_VAR) {
+            http.timeout_secs = Some(parse_secs_env(TIMEOUT_ENV_VAR, &secs)?);
+        }
+
+        if let Some(proxy) = matches.value_of("proxy") {
+            http.proxy = Some(proxy.to_string());
+        }
+        if let Some(no_proxy) = matches.values_of("no-proxy") {
+            http.no_proxy = no_proxy.flat_map(split_list).collect();
+        }
+        if let Some(certs) = matches.values_of("ca-cert") {
+            http.ca_certs = certs.map(PathBuf::from).collect();
+        }
+        if let Some(cert) = matches.value_of("client-cert") {
+            http.client_cert = Some(PathBuf::from(cert));
+        }
+        // all were checked by their validators
+        if let Some(secs) = matches.value_of("connect-timeout") {
+            http.connect_timeout_secs = Some(secs.parse().unwrap());
+        }
+        if let Some(secs) = matches.value_of("read-timeout") {
+            http.read_timeout_secs = Some(secs.parse().unwrap());
+        }
+        if let Some(secs) = matches.value_of("timeout") {
+            http.timeout_secs = Some(secs.parse().unwrap());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::app;
+
+    // options beat env vars, which beat the config file. env vars are only
+    // set here, so this can't race with other tests.
+    #[test]
+    fn precedence() {
+        let path = env::temp_dir().join(format!("config-{}.yaml", std::process::id()));
+        fs::write(
+            &path,
+            "http:\n  proxy: http://file:3128\n  connect_timeout_secs: 5\n  read_timeout_secs: 7\n  timeout_secs: 10\nredact_keys: [file]\n",
+        )
+        .unwrap();
+        env::set_var(PROXY_ENV_VAR, "http://env:3128");
+        env::set_var(READ_TIMEOUT_ENV_VAR, "8");
+        env::set_var(TIMEOUT_ENV_VAR, "20");
+        let matches = app().get_matches_from(vec![
+            "platformapi-namespace",
+            "gc",
+            "--productkey",
+            "demo",
+            "--all",
+            "--config",
+            path.to_str().unwrap(),
+            "--timeout",
+            "30",
+        ]);
+        let config = Config::from_matches(matches.subcommand_matches("gc").unwrap());
+        env::remove_var(PROXY_ENV_VAR);
+        env::remove_var(READ_TIMEOUT_ENV_VAR);
+        env::remove_var(TIMEOUT_ENV_VAR);
+        fs::remove_file(&path).unwrap();
+
+        let config = config.unwrap();
+        assert_eq!(config.http.proxy.as_deref(), Some("http://env:3128"));
+        assert_eq!(config.http.connect_timeout_secs, Some(5));
+        assert_eq!(config.http.read_timeout_secs, Some(8));
+        assert_eq!(config.http.timeout_secs, Some(30));
+        assert_eq!(config.redact_keys, Some(vec!["file".to_string()]));
+    }
+
+    #[test]
+    fn missing_config_file() {
+        match Config::load(Some("/nonexistent/config.yaml")) {
+            Err(Error::Config(path, _)) => {
+                assert_eq!(path, PathBuf::from("/nonexistent/config.yaml"))
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
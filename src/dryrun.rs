@@ -0,0 +1,301 @@
+use clap::ArgMatches;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::secret::{self, REDACTED};
+
+pub enum Source {
+    Option(&'static str),
+    Env(&'static str),
+    Unset(&'static str, &'static str),
+}
+
+// a config value along with where it was read from, for reporting in dry-runs
+pub struct Setting {
+    pub value: Option<String>,
+    pub source: Source,
+}
+
+impl Setting {
+    pub fn resolve(matches: &ArgMatches<'_>, opt: &'static str, var: &'static str) -> Self {
+        if let Some(val) = matches.value_of(opt) {
+            Setting {
+                value: Some(val.to_string()),
+                source: Source::Option(opt),
+            }
+        } else if let Ok(val) = env::var(var) {
+            Setting {
+                value: Some(val),
+                source: Source::Env(var),
+            }
+        } else {
+            Setting {
+                value: None,
+                source: Source::Unset(opt, var),
+            }
+        }
+    }
+
+    pub fn value_or_placeholder(&self) -> String {
+        match &self.value {
+            Some(v) => v.clone(),
+            None => format!("<{}>", self.name()),
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self.source {
+            Source::Option(opt) => opt,
+            Source::Env(var) => var,
+            Source::Unset(opt, _) => opt,
+        }
+    }
+}
+
+impl fmt::Display for Setting {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.value, &self.source) {
+            (Some(v), Source::Option(opt)) => write!(f, "{} (from --{} option)", v, opt),
+            (Some(v), Source::Env(var)) => write!(f, "{} (from {} env var)", v, var),
+            (_, Source::Unset(opt, var)) => write!(f, "unset (neither --{} nor {})", opt, var),
+            (None, _) => write!(f, "unset"),
+        }
+    }
+}
+
+fn env_presence(var: &str, secret: bool) -> String {
+    match env::var(var) {
+        Ok(_) if secret => format!("{} (from {} env var)", REDACTED, var),
+        Ok(v) => format!("{} (from {} env var)", v, var),
+        Err(_) => format!("unset ({} env var)", var),
+    }
+}
+
+// payloads are shown with the same keys hidden as when they're logged
+fn redacted<T: Serialize>(payload: &T) -> Value {
+    let mut value = serde_json::to_value(payload).unwrap();
+    secret::redact(&mut value);
+    value
+}
+
+// rendered files are redacted document by document. anything which can't be
+// parsed isn't shown at all, as there's no telling what it contains.
+fn redacted_documents(rendered: &str) -> Option<String> {
+    let mut docs = Vec::new();
+    for doc in serde_yaml::Deserializer::from_str(rendered) {
+        let mut value = Value::deserialize(doc).ok()?;
+        secret::redact(&mut value);
+        docs.push(serde_yaml::to_string(&value).ok()?);
+    }
+    Some(docs.concat())
+}
+
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+pub struct DryRun<'a> {
+    pub settings: Vec<(&'static str, &'a Setting)>,
+    pub method: &'static str,
+    pub url: String,
+    pub rendered: Vec<(String, String)>,
+}
+
+impl<'a> DryRun<'a> {
+    fn write_settings(&self, out: &mut dyn Write) -> io::Result<()> {
+        writeln!(out, "Resolved configuration:")?;
+        for (name, setting) in &self.settings {
+            writeln!(out, "  {}: {}", name, setting)?;
+        }
+        writeln!(out, "  client id: {}", env_presence("CLIENT_ID", false))?;
+        writeln!(
+            out,
+            "  client secret: {}",
+            env_presence("CLIENT_SECRET", true)
+        )?;
+        writeln!(out, "  scope: {}", env_presence("SCOPE", false))?;
+        for (source, rendered) in &self.rendered {
+            match redacted_documents(rendered) {
+                Some(rendered) => writeln!(out, "Rendered {}:\n{}", source, rendered.trim_end())?,
+                None => writeln!(out, "Rendered {}: not shown, could not be parsed", source)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_request<T: Serialize>(&self, out: &mut dyn Write, payload: &T) -> io::Result<()> {
+        let payload = redacted(payload);
+        writeln!(out, "Request:")?;
+        writeln!(out, "  {} {}", self.method, self.url)?;
+        writeln!(out, "  Authorization: Bearer {}", REDACTED)?;
+        writeln!(out, "  Content-Type: application/json")?;
+        writeln!(
+            out,
+            "Would submit the following payload to the API:\n{}",
+            serde_json::to_string_pretty(&payload).unwrap()
+        )?;
+        writeln!(out, "Equivalent curl command:")?;
+        writeln!(
+            out,
+            "  curl -X {} {} -H \"Authorization: Bearer $TOKEN\" -H 'Content-Type: application/json' --data-binary {}",
+            self.method,
+            shell_quote(&self.url),
+            shell_quote(&serde_json::to_string(&payload).unwrap())
+        )
+    }
+
+    // fails the same way println! would
+    pub fn report_settings(&self) {
+        self.write_settings(&mut io::stdout())
+            .expect("failed printing to stdout");
+    }
+
+    pub fn report_request<T: Serialize>(&self, payload: &T) {
+        self.write_request(&mut io::stdout(), payload)
+            .expect("failed printing to stdout");
+    }
+
+    // print everything we would have done, without touching the network
+    pub fn report<T: Serialize>(&self, payload: &T) {
+        self.report_settings();
+        self.report_request(payload);
+        eprintln!("Dry-run, not calling API!");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::app;
+    use serde_json::json;
+
+    // env vars only used here, so this can't race with other tests
+    const OPTION_VAR: &str = "DRYRUN_TEST_OPTION";
+    const ENV_VAR: &str = "DRYRUN_TEST_ENV";
+    const UNSET_VAR: &str = "DRYRUN_TEST_UNSET";
+
+    #[test]
+    fn resolve_sources() {
+        let matches = app().get_matches_from(vec![
+            "platformapi-namespace",
+            "create",
+            "--cluster",
+            "from-option",
+            "demo",
+            "app",
+        ]);
+        let matches = matches.subcommand_matches("create").unwrap();
+        env::set_var(OPTION_VAR, "from-env");
+        env::set_var(ENV_VAR, "from-env");
+        let option = Setting::resolve(matches, "cluster", OPTION_VAR);
+        let from_env = Setting::resolve(matches, "tenant", ENV_VAR);
+        let unset = Setting::resolve(matches, "hostname", UNSET_VAR);
+        env::remove_var(OPTION_VAR);
+        env::remove_var(ENV_VAR);
+
+        assert_eq!(option.value.as_deref(), Some("from-option"));
+        assert_eq!(option.to_string(), "from-option (from --cluster option)");
+        assert_eq!(from_env.value.as_deref(), Some("from-env"));
+        assert_eq!(
+            from_env.to_string(),
+            format!("from-env (from {} env var)", ENV_VAR)
+        );
+        assert_eq!(unset.value, None);
+        assert_eq!(unset.value_or_placeholder(), "<hostname>");
+        assert_eq!(
+            unset.to_string(),
+            format!("unset (neither --hostname nor {})", UNSET_VAR)
+        );
+    }
+
+    #[test]
+    fn report_output() {
+        let cluster = Setting {
+            value: Some("cluster-a".to_string()),
+            source: Source::Option("cluster"),
+        };
+        let tenant = Setting {
+            value: None,
+            source: Source::Unset("tenant", "PLATFORM_API_TENANT"),
+        };
+        let dry_run = DryRun {
+            settings: vec![("cluster", &cluster), ("tenant", &tenant)],
+            method: "POST",
+            url: "https://api.example.com/v1/namespace".to_string(),
+            rendered: vec![("manifest".to_string(), "name: app\n".to_string())],
+        };
+        let mut out = Vec::new();
+        dry_run.write_settings(&mut out).unwrap();
+        let payload = json!({"namespace": "app", "note": "it's here"});
+        dry_run.write_request(&mut out, &payload).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert!(out.contains("  cluster: cluster-a (from --cluster option)\n"));
+        assert!(out.contains("  tenant: unset (neither --tenant nor PLATFORM_API_TENANT)\n"));
+        assert!(out.contains("Rendered manifest:\n---\nname: app\n"));
+        assert!(out.contains("  POST https://api.example.com/v1/namespace\n"));
+        assert!(out.contains(&format!("  Authorization: Bearer {}\n", REDACTED)));
+        assert!(out.contains(
+            "  curl -X POST 'https://api.example.com/v1/namespace' -H \"Authorization: Bearer $TOKEN\" \
+             -H 'Content-Type: application/json' \
+             --data-binary '{\"namespace\":\"app\",\"note\":\"it'\\''s here\"}'\n"
+        ));
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+        assert_eq!(shell_quote("''"), "''\\'''\\'''");
+    }
+
+    #[test]
+    fn payload_is_redacted() {
+        let payload = json!({"productkey": "demo", "db_password": "hunter2"});
+        assert_eq!(
+            redacted(&payload),
+            json!({"productkey": "demo", "db_password": REDACTED})
+        );
+    }
+
+    #[test]
+    fn rendered_documents_are_redacted() {
+        let rendered = "name: app\napi_key: abc123\n---\nname: other\n";
+        let shown = redacted_documents(rendered).unwrap();
+        assert!(!shown.contains("abc123"));
+        assert!(shown.contains(REDACTED));
+        assert!(shown.contains("other"));
+        assert_eq!(redacted_documents("key: [unterminated"), None);
+    }
+
+    #[test]
+    fn configured_keys_are_redacted() {
+        // the defaults are kept so tests running alongside see no difference
+        let keys = [
+            "secret",
+            "password",
+            "credential",
+            "private_key",
+            "api_key",
+            "_token",
+            "ticket_ref",
+        ];
+        secret::set_redact_keys(Some(keys.iter().map(|k| k.to_string()).collect()));
+        let dry_run = DryRun {
+            settings: vec![],
+            method: "POST",
+            url: "https://api.example.com/namespace".to_string(),
+            rendered: vec![],
+        };
+        let mut out = Vec::new();
+        let payload = json!({"productkey": "demo", "ticket_ref": "INC-1234"});
+        dry_run.write_request(&mut out, &payload).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(!out.contains("INC-1234"));
+        assert!(out.contains(&format!("\"ticket_ref\": \"{}\"", REDACTED)));
+        assert!(out.contains("demo"));
+    }
+}
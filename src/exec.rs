@@ -0,0 +1,146 @@
+use log::info;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGQUIT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::os::unix::process::ExitStatusExt;
+use std::process::Command;
+use std::thread;
+
+use crate::types::Error;
+
+// run a command to completion with the given extra environment, returning its
+// exit code (or 128 + signal number if it was killed by a signal).
+//
+// while the child runs we catch termination signals so that we survive long
+// enough to clean up afterwards, and forward them to the child. SIGINT and
+// SIGQUIT may only have been sent to us, eg. by a CI runner, in which case the
+// child would otherwise never stop. from a terminal the child gets them twice,
+// which programs treat the same as once.
+pub fn run_command(command: &[&str], env: &[(&str, &str)]) -> Result<i32, Error> {
+    let (program, args) = match command.split_first() {
+        Some(split) => split,
+        None => return Err(Error::Unknown("no command given to exec".to_string())),
+    };
+    let mut signals = Signals::new([SIGHUP, SIGINT, SIGQUIT, SIGTERM])
+        .map_err(|e| Error::Unknown(format!("Could not register signal handlers: {}", e)))?;
+    let mut child = Command::new(program)
+        .args(args)
+        .envs(env.iter().cloned())
+        .spawn()
+        .map_err(|e| Error::Unknown(format!("Could not run '{}': {}", program, e)))?;
+    let pid = child.id() as libc::pid_t;
+    let handle = signals.handle();
+    let forwarder = thread::spawn(move || {
+        for sig in signals.forever() {
+            info!("forwarding signal {} to child {}", sig, pid);
+            unsafe {
+                libc::kill(pid, sig);
+            }
+        }
+    });
+    let status = child.wait();
+    handle.close();
+    forwarder.join().expect("signal forwarding thread panicked");
+    let status =
+        status.map_err(|e| Error::Unknown(format!("Error waiting on '{}': {}", program, e)))?;
+    Ok(match (status.code(), status.signal()) {
+        (Some(code), _) => code,
+        (None, Some(sig)) => 128 + sig,
+        (None, None) => 1,
+    })
+}
+
+// run a command against a namespace created for it, then call `cleanup` with
+// its exit code. cleanup also runs when the command couldn't be started at
+// all (with no exit code) so the namespace isn't leaked, and the original
+// error is returned afterwards. a failed cleanup is reported, and turns a
+// successful exit code into 1.
+pub fn run_with_cleanup<F>(command: &[&str], env: &[(&str, &str)], cleanup: F) -> Result<i32, Error>
+where
+    F: FnOnce(Option<i32>) -> Result<(), Error>,
+{
+    let result = run_command(command, env);
+    let cleaned = cleanup(result.as_ref().ok().copied());
+    if let Err(e) = &cleaned {
+        eprintln!("{}", e);
+    }
+    let code = result?;
+    Ok(match cleaned {
+        Err(_) if code == 0 => 1,
+        _ => code,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    // a signal sent to the test process is forwarded to every running child,
+    // so commands are run one at a time
+    static RUNNING: Mutex<()> = Mutex::new(());
+
+    fn serialize() -> MutexGuard<'static, ()> {
+        RUNNING.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[test]
+    fn cleanup_after_spawn_failure() {
+        let _running = serialize();
+        let mut cleaned = Vec::new();
+        let result = run_with_cleanup(&["/nonexistent/command"], &[], |code| {
+            cleaned.push(code);
+            Ok(())
+        });
+        assert_eq!(cleaned, vec![None]);
+        match result {
+            Err(Error::Unknown(msg)) => assert!(msg.starts_with("Could not run")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn cleanup_gets_exit_code() {
+        let _running = serialize();
+        let mut cleaned = Vec::new();
+        let result = run_with_cleanup(&["sh", "-c", "exit $CODE"], &[("CODE", "3")], |code| {
+            cleaned.push(code);
+            Ok(())
+        });
+        assert_eq!(cleaned, vec![Some(3)]);
+        assert_eq!(result.unwrap(), 3);
+    }
+
+    #[test]
+    fn forwards_sigint() {
+        let _running = serialize();
+        let marker = std::env::temp_dir().join(format!("exec-ready-{}", std::process::id()));
+        let _ = std::fs::remove_file(&marker);
+        let watched = marker.clone();
+        // signal ourselves, as a CI runner would, once the child is ready
+        let signaller = thread::spawn(move || {
+            while !watched.exists() {
+                thread::sleep(std::time::Duration::from_millis(10));
+            }
+            unsafe {
+                libc::kill(libc::getpid(), SIGINT);
+            }
+        });
+        let script = "trap 'exit 7' INT; touch \"$MARKER\"; while :; do sleep 0.05; done";
+        let code = run_command(
+            &["sh", "-c", script],
+            &[("MARKER", marker.to_str().unwrap())],
+        );
+        signaller.join().unwrap();
+        std::fs::remove_file(&marker).unwrap();
+        assert_eq!(code.unwrap(), 7);
+    }
+
+    #[test]
+    fn failed_cleanup_fails_command() {
+        let _running = serialize();
+        let result = run_with_cleanup(&["true"], &[], |_| {
+            Err(Error::Unknown("delete failed".to_string()))
+        });
+        assert_eq!(result.unwrap(), 1);
+    }
+}
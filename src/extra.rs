@@ -0,0 +1,343 @@
+use clap::ArgMatches;
+use serde_json::{Map, Value};
+
+use crate::interpolate::Interpolation;
+use crate::schema::{self, Kind};
+use crate::types::{Error, ExtraProps};
+
+// json merge patch (RFC 7386) semantics: objects are merged recursively, a
+// null removes the key and anything else replaces the existing value
+pub fn merge(base: &mut Value, patch: Value) {
+    match patch {
+        Value::Object(patch) => {
+            if !base.is_object() {
+                *base = Value::Object(Map::new());
+            }
+            let base = base.as_object_mut().unwrap();
+            for (k, v) in patch {
+                if v.is_null() {
+                    base.remove(&k);
+                } else {
+                    merge(base.entry(k).or_insert(Value::Null), v);
+                }
+            }
+        }
+        other => *base = other,
+    }
+}
+
+// split a dotted path such as 'quota.cpu', where '\.' is a literal dot
+fn parse_path(path: &str) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some(next) => parts.last_mut().unwrap().push(next),
+                None => parts.last_mut().unwrap().push('\\'),
+            },
+            '.' => parts.push(String::new()),
+            c => parts.last_mut().unwrap().push(c),
+        }
+    }
+    parts
+}
+
+fn set_path(root: &mut Value, path: &[String], value: Value) {
+    match path.split_first() {
+        None => *root = value,
+        Some((key, rest)) => {
+            if !root.is_object() {
+                *root = Value::Object(Map::new());
+            }
+            let child = root
+                .as_object_mut()
+                .unwrap()
+                .entry(key.clone())
+                .or_insert(Value::Null);
+            set_path(child, rest, value);
+        }
+    }
+}
+
+fn unset_path(root: &mut Value, path: &[String]) {
+    match path.split_first() {
+        None => {}
+        Some((key, [])) => {
+            if let Some(obj) = root.as_object_mut() {
+                obj.remove(key);
+            }
+        }
+        Some((key, rest)) => {
+            if let Some(child) = root.get_mut(key) {
+                unset_path(child, rest);
+            }
+        }
+    }
+}
+
+// nulls delete keys when layers are merged, so they're left out when a
+// layer is checked on its own
+fn without_nulls(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k.clone(), without_nulls(v)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn option_error(opt: &str, val: &str, msg: String) -> Error {
+    Error::Option(opt.to_string(), val.to_string(), msg)
+}
+
+fn read_extra_data(val: &str, interp: &Interpolation) -> Result<Value, Error> {
+    let parsed = if let Some(filename) = val.strip_prefix('@') {
+        let contents = std::fs::read_to_string(filename)
+            .map_err(|e| option_error("extra-data", val, e.to_string()))?;
+        serde_yaml::from_str(&interp.render(val, contents)?)
+    } else {
+        serde_yaml::from_str(val)
+    };
+    match parsed.map_err(|e| option_error("extra-data", val, e.to_string()))? {
+        // warnings wait until everything is merged, so they're only shown once
+        v @ Value::Object(_) => {
+            let report = schema::validate(Kind::ExtraData, &without_nulls(&v));
+            if report.errors.is_empty() {
+                Ok(v)
+            } else {
+                Err(option_error("extra-data", val, report.describe()))
+            }
+        }
+        Value::Null => Ok(Value::Object(Map::new())),
+        _ => Err(option_error(
+            "extra-data",
+            val,
+            "expected a mapping of properties".to_string(),
+        )),
+    }
+}
+
+fn split_assignment<'a>(opt: &str, val: &'a str) -> Result<(Vec<String>, &'a str), Error> {
+    match val.split_once('=') {
+        Some((path, value)) if !path.is_empty() => Ok((parse_path(path), value)),
+        _ => Err(option_error(
+            opt,
+            val,
+            "expected key.path=value".to_string(),
+        )),
+    }
+}
+
+enum Layer<'a> {
+    Data(&'a str),
+    Set(&'a str),
+    SetJson(&'a str),
+    Unset(&'a str),
+}
+
+// collect occurrences of an option along with their position on the command line
+fn layers<'a>(
+    matches: &'a ArgMatches<'_>,
+    name: &str,
+    f: fn(&'a str) -> Layer<'a>,
+) -> Vec<(usize, Layer<'a>)> {
+    match (matches.indices_of(name), matches.values_of(name)) {
+        (Some(indices), Some(values)) => indices.zip(values.map(f)).collect(),
+        _ => Vec::new(),
+    }
+}
+
+// layer every --extra-data, --set, --set-json and --unset in the order given
+pub fn extra_from_matches(
+    matches: &ArgMatches<'_>,
+    interp: &Interpolation,
+) -> Result<ExtraProps, Error> {
+    let mut all = layers(matches, "extra-props", Layer::Data);
+    all.extend(layers(matches, "set", Layer::Set));
+    all.extend(layers(matches, "set-json", Layer::SetJson));
+    all.extend(layers(matches, "unset", Layer::Unset));
+    all.sort_by_key(|(idx, _)| *idx);
+    let mut merged = Value::Object(Map::new());
+    for (_, layer) in all {
+        match layer {
+            Layer::Data(val) => merge(&mut merged, read_extra_data(val, interp)?),
+            Layer::Set(val) => {
+                let (path, value) = split_assignment("set", val)?;
+                let value = serde_yaml::from_str(value)
+                    .unwrap_or_else(|_| Value::String(value.to_string()));
+                set_path(&mut merged, &path, value);
+            }
+            Layer::SetJson(val) => {
+                let (path, value) = split_assignment("set-json", val)?;
+                let value = serde_json::from_str(value)
+                    .map_err(|e| option_error("set-json", val, e.to_string()))?;
+                set_path(&mut merged, &path, value);
+            }
+            Layer::Unset(val) => unset_path(&mut merged, &parse_path(val)),
+        }
+    }
+    // --set and --set-json values only get checked here
+    let report = schema::validate(Kind::ExtraData, &merged);
+    report.warn("extra data");
+    if !report.errors.is_empty() {
+        return Err(Error::Schema("extra data".to_string(), report.describe()));
+    }
+    match merged {
+        Value::Object(map) => Ok(map.into_iter().collect()),
+        _ => unreachable!("merged extra data is always an object"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::app;
+    use serde_json::json;
+
+    fn merged(target: Value, patch: Value) -> Value {
+        let mut target = target;
+        merge(&mut target, patch);
+        target
+    }
+
+    // the examples from appendix A of RFC 7386
+    #[test]
+    fn rfc7386_examples() {
+        let examples = vec![
+            (json!({"a": "b"}), json!({"a": "c"}), json!({"a": "c"})),
+            (
+                json!({"a": "b"}),
+                json!({"b": "c"}),
+                json!({"a": "b", "b": "c"}),
+            ),
+            (json!({"a": "b"}), json!({"a": null}), json!({})),
+            (
+                json!({"a": "b", "b": "c"}),
+                json!({"a": null}),
+                json!({"b": "c"}),
+            ),
+            (json!({"a": ["b"]}), json!({"a": "c"}), json!({"a": "c"})),
+            (json!({"a": "c"}), json!({"a": ["b"]}), json!({"a": ["b"]})),
+            (
+                json!({"a": {"b": "c"}}),
+                json!({"a": {"b": "d", "c": null}}),
+                json!({"a": {"b": "d"}}),
+            ),
+            (
+                json!({"a": [{"b": "c"}]}),
+                json!({"a": [1]}),
+                json!({"a": [1]}),
+            ),
+            (json!(["a", "b"]), json!(["c", "d"]), json!(["c", "d"])),
+            (json!({"a": "b"}), json!(["c"]), json!(["c"])),
+            (json!({"a": "foo"}), json!(null), json!(null)),
+            (json!({"a": "foo"}), json!("bar"), json!("bar")),
+            (
+                json!({"e": null}),
+                json!({"a": 1}),
+                json!({"e": null, "a": 1}),
+            ),
+            (
+                json!([1, 2]),
+                json!({"a": "b", "c": null}),
+                json!({"a": "b"}),
+            ),
+            (
+                json!({}),
+                json!({"a": {"bb": {"ccc": null}}}),
+                json!({"a": {"bb": {}}}),
+            ),
+        ];
+        for (target, patch, expected) in examples {
+            assert_eq!(
+                merged(target.clone(), patch.clone()),
+                expected,
+                "{} patched with {}",
+                target,
+                patch
+            );
+        }
+    }
+
+    #[test]
+    fn paths() {
+        assert_eq!(parse_path("quota.cpu"), vec!["quota", "cpu"]);
+        assert_eq!(
+            parse_path(r"annotations.example\.com/owner"),
+            vec!["annotations", "example.com/owner"]
+        );
+        assert_eq!(parse_path(r"trailing\"), vec![r"trailing\"]);
+
+        let mut root = json!({"quota": "flat"});
+        set_path(&mut root, &parse_path("quota.cpu"), json!(4));
+        set_path(&mut root, &parse_path("a.b.c"), json!(true));
+        assert_eq!(root, json!({"quota": {"cpu": 4}, "a": {"b": {"c": true}}}));
+        unset_path(&mut root, &parse_path("a.b.c"));
+        unset_path(&mut root, &parse_path("quota.cpu.nested"));
+        unset_path(&mut root, &parse_path("missing.key"));
+        assert_eq!(root, json!({"quota": {"cpu": 4}, "a": {"b": {}}}));
+    }
+
+    fn extra(args: &[&str]) -> Result<ExtraProps, Error> {
+        let mut argv = vec!["platformapi-namespace", "create", "--ttl", "1h"];
+        argv.extend_from_slice(args);
+        argv.extend_from_slice(&["demo", "app"]);
+        let matches = app().get_matches_from(argv);
+        extra_from_matches(
+            matches.subcommand_matches("create").unwrap(),
+            &Interpolation::new(false, false),
+        )
+    }
+
+    // later options win, whichever kind they are
+    #[test]
+    fn layered_in_command_line_order() {
+        let props = extra(&[
+            "--set",
+            "quota.cpu=1",
+            "--extra-data",
+            "{quota: {cpu: '2', memory: 4Gi}, owner: me}",
+            "--unset",
+            "owner",
+            "--set-json",
+            r#"tags=["a", "b"]"#,
+            "--extra-data",
+            "{quota: {memory: null}}",
+            "--set",
+            "note=plain text",
+        ])
+        .unwrap();
+        assert_eq!(props["quota"], json!({"cpu": "2"}));
+        assert_eq!(props["tags"], json!(["a", "b"]));
+        assert_eq!(props["note"], json!("plain text"));
+        assert!(!props.contains_key("owner"));
+
+        let props = extra(&[
+            "--extra-data",
+            "{quota: {cpu: '2'}}",
+            "--set",
+            "quota.cpu=1",
+        ])
+        .unwrap();
+        assert_eq!(props["quota"], json!({"cpu": 1}));
+    }
+
+    #[test]
+    fn malformed_options() {
+        for args in &[
+            vec!["--set", "=1"],
+            vec!["--set", "novalue"],
+            vec!["--set-json", "a={"],
+            vec!["--extra-data", "[1, 2]"],
+        ] {
+            match extra(args) {
+                Err(Error::Option(..)) => {}
+                other => panic!("{:?} gave {:?}", args, other),
+            }
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use crate::secret::{self, REDACTED};
+use crate::transport::{Body, Method, Request, Response, Transport, TransportError};
+use crate::types::Error;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: Method,
+    url: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    bearer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    json: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    form: Option<BTreeMap<String, String>>,
+}
+
+// response bodies are kept as parsed JSON where possible so fixtures are
+// readable, otherwise as text
+#[derive(Debug, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    json: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RecordedError {
+    Timeout,
+    Other(String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Exchange {
+    request: RecordedRequest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    response: Option<RecordedResponse>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<RecordedError>,
+}
+
+impl Exchange {
+    fn new(req: &Request, result: &Result<Response, TransportError>) -> Self {
+        let (json, form) = match &req.body {
+            Some(Body::Json(body)) => {
+                let mut json =
+                    serde_json::from_str(body).unwrap_or_else(|_| Value::String(body.clone()));
+                secret::redact(&mut json);
+                (Some(json), None)
+            }
+            Some(Body::Form(body)) => {
+                let mut form: BTreeMap<String, String> =
+                    serde_urlencoded::from_str(body.expose()).unwrap_or_default();
+                for (k, v) in form.iter_mut() {
+                    if secret::is_sensitive(k) {
+                        *v = REDACTED.to_string();
+                    }
+                }
+                (None, Some(form))
+            }
+            None => (None, None),
+        };
+        let mut exchange = Exchange {
+            request: RecordedRequest {
+                method: req.method,
+                url: req.url.clone(),
+                bearer: req.bearer.as_ref().map(|_| REDACTED.to_string()),
+                json,
+                form,
+            },
+            response: None,
+            error: None,
+        };
+        match result {
+            Ok(resp) => {
+                let (json, text) = match serde_json::from_str::<Value>(&resp.body) {
+                    Ok(mut json) => {
+                        secret::redact(&mut json);
+                        (Some(json), None)
+                    }
+                    Err(_) => (None, Some(resp.body.clone())),
+                };
+                exchange.response = Some(RecordedResponse {
+                    status: resp.status,
+                    json,
+                    text,
+                });
+            }
+            Err(TransportError::Timeout) => exchange.error = Some(RecordedError::Timeout),
+            Err(TransportError::Other(e)) => exchange.error = Some(RecordedError::Other(e.clone())),
+        }
+        exchange
+    }
+
+    fn replay(&self) -> Result<Response, TransportError> {
+        match (&self.response, &self.error) {
+            (Some(resp), _) => Ok(Response {
+                status: resp.status,
+                body: match (&resp.json, &resp.text) {
+                    (Some(json), _) => json.to_string(),
+                    (None, Some(text)) => text.clone(),
+                    (None, None) => String::new(),
+                },
+            }),
+            (None, Some(RecordedError::Timeout)) => Err(TransportError::Timeout),
+            (None, Some(RecordedError::Other(e))) => Err(TransportError::Other(e.clone())),
+            (None, None) => Err(TransportError::Other(
+                "fixture has neither a response nor an error".to_string(),
+            )),
+        }
+    }
+}
+
+// 'https://host/namespace/demo-test' -> 'namespace-demo-test'
+fn slug(url: &str) -> String {
+    let path = url
+        .split_once("://")
+        .map_or(url, |(_, rest)| rest)
+        .split_once('/')
+        .map_or("", |(_, path)| path);
+    let path = path.split('?').next().unwrap_or_default();
+    let slug: String = path
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect();
+    slug.trim_matches('-').to_string()
+}
+
+fn fixture_paths(dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let read_err = |e: std::io::Error| {
+        Error::Unknown(format!(
+            "Could not read fixture directory {}: {}",
+            dir.display(),
+            e
+        ))
+    };
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .map_err(read_err)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<_, _>>()
+        .map_err(read_err)?;
+    paths.retain(|p| p.extension() == Some("json".as_ref()));
+    paths.sort();
+    Ok(paths)
+}
+
+// passes every request through to `inner`, writing each exchange to a
+// numbered fixture file in `dir`
+pub struct RecordingTransport<T> {
+    inner: T,
+    dir: PathBuf,
+    count: AtomicUsize,
+}
+
+impl<T: Transport> RecordingTransport<T> {
+    pub fn new(inner: T, dir: &Path) -> Result<Self, Error> {
+        fs::create_dir_all(dir).map_err(|e| {
+            Error::Unknown(format!(
+                "Could not create fixture directory {}: {}",
+                dir.display(),
+                e
+            ))
+        })?;
+        // keep numbering after any fixtures already there, so several
+        // commands can be recorded into one directory
+        let existing = fixture_paths(dir)?.len();
+        Ok(RecordingTransport {
+            inner,
+            dir: dir.to_path_buf(),
+            count: AtomicUsize::new(existing),
+        })
+    }
+}
+
+impl<T: Transport> Transport for RecordingTransport<T> {
+    fn send(&self, req: Request) -> Result<Response, TransportError> {
+        let recorded = req.clone();
+        let result = self.inner.send(req);
+        let exchange = Exchange::new(&recorded, &result);
+        let idx = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+        let path = self.dir.join(format!(
+            "{:04}-{}-{}.json",
+            idx,
+            recorded.method.as_str().to_lowercase(),
+            slug(&recorded.url)
+        ));
+        match serde_json::to_string_pretty(&exchange) {
+            Ok(contents) => match fs::write(&path, contents + "\n") {
+                Ok(()) => info!("recorded {}", path.display()),
+                Err(e) => warn!("could not write fixture {}: {}", path.display(), e),
+            },
+            Err(e) => warn!("could not serialize fixture {}: {}", path.display(), e),
+        }
+        result
+    }
+}
+
+// serves responses from fixtures written by RecordingTransport. each fixture
+// is used once, matched on method and URL in the order they were recorded.
+pub struct ReplayTransport {
+    dir: PathBuf,
+    exchanges: Mutex<Vec<Option<Exchange>>>,
+}
+
+impl ReplayTransport {
+    pub fn new(dir: &Path) -> Result<Self, Error> {
+        let paths = fixture_paths(dir)?;
+        let exchanges = paths
+            .iter()
+            .map(|path| {
+                let contents = fs::read_to_string(path).map_err(|e| {
+                    Error::Unknown(format!("Could not read fixture {}: {}", path.display(), e))
+                })?;
+                serde_json::from_str(&contents).map(Some).map_err(|e| {
+                    Error::Unknown(format!("Could not parse fixture {}: {}", path.display(), e))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(ReplayTransport {
+            dir: dir.to_path_buf(),
+            exchanges: Mutex::new(exchanges),
+        })
+    }
+}
+
+impl Transport for ReplayTransport {
+    fn send(&self, req: Request) -> Result<Response, TransportError> {
+        let mut exchanges = self.exchanges.lock().unwrap();
+        let found = exchanges.iter_mut().find(|e| match e {
+            Some(e) => e.request.method == req.method && e.request.url == req.url,
+            None => false,
+        });
+        match found.and_then(Option::take) {
+            Some(exchange) => exchange.replay(),
+            None => Err(TransportError::Other(format!(
+                "no unused fixture in {} for {} {}",
+                self.dir.display(),
+                req.method.as_str(),
+                req.url
+            ))),
+        }
+    }
+
+    fn is_replay(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{self, BaseUrl};
+    use crate::capabilities::{Cache, Capabilities};
+    use crate::transport::fake::FakeTransport;
+    use crate::types::{NSDefBuilder, Token};
+    use std::env;
+
+    fn fixture_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("fixtures-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn record_then_replay() {
+        let dir = fixture_dir("roundtrip");
+        let fake = FakeTransport::new()
+            .respond(200, r#"{"token_type": "Bearer", "access_token": "abc"}"#)
+            .respond(503, "unavailable")
+            .fail(TransportError::Timeout);
+        let recorder = RecordingTransport::new(fake, &dir).unwrap();
+        let token_req = Request::new(
+            Method::Post,
+            "https://login.example.com/tenant/oauth2/v2.0/token".to_string(),
+        )
+        .body(Body::Form(
+            "client_id=me&client_secret=shh&grant_type=client_credentials".into(),
+        ));
+        let create_req = Request::new(Method::Post, "https://api/namespace".to_string())
+            .bearer("abc")
+            .body(Body::Json(r#"{"namespace": "test"}"#.to_string()));
+        recorder.send(token_req.clone()).unwrap();
+        recorder.send(create_req.clone()).unwrap();
+        assert!(recorder.send(create_req.clone()).is_err());
+
+        let written: Vec<String> = fixture_paths(&dir)
+            .unwrap()
+            .iter()
+            .map(|p| fs::read_to_string(p).unwrap())
+            .collect();
+        assert_eq!(written.len(), 3);
+        assert!(written
+            .iter()
+            .all(|f| !f.contains("shh") && !f.contains("abc")));
+
+        let replay = ReplayTransport::new(&dir).unwrap();
+        let token = replay.send(token_req).unwrap();
+        assert_eq!(token.status, 200);
+        let token: Value = serde_json::from_str(&token.body).unwrap();
+        assert_eq!(token["access_token"], REDACTED);
+        assert_eq!(replay.send(create_req.clone()).unwrap().status, 503);
+        assert!(matches!(
+            replay.send(create_req.clone()),
+            Err(TransportError::Timeout)
+        ));
+        assert!(matches!(
+            replay.send(create_req),
+            Err(TransportError::Other(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn replay_versioned_api() {
+        let dir = fixture_dir("versioned");
+        let base = BaseUrl::parse("https://api.example.com").unwrap();
+        let token: Token =
+            serde_json::from_str(r#"{"token_type": "Bearer", "access_token": "abc"}"#).unwrap();
+        let payload = NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("1h")
+            .cluster("cluster")
+            .namespace("app")
+            .build()
+            .unwrap();
+        let created = r#"{"message": "created", "namespace": "demo-app", "expiry": "soon"}"#;
+        let negotiate_and_create = |transport: &dyn Transport| {
+            let caps = Capabilities::probe(transport, &base, &token, Cache::select(true, false));
+            let versioned = caps.negotiate(&base).unwrap();
+            api::create(transport, &versioned, &token, &payload).unwrap()
+        };
+
+        let fake = FakeTransport::new()
+            .respond(200, r#"{"api_versions": ["v1"]}"#)
+            .respond(200, created);
+        let recorder = RecordingTransport::new(fake, &dir).unwrap();
+        negotiate_and_create(&recorder);
+
+        let replay = ReplayTransport::new(&dir).unwrap();
+        assert_eq!(negotiate_and_create(&replay).namespace, "demo-app");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn slug_from_url() {
+        assert_eq!(
+            slug("https://api.example.com/namespace/demo-test"),
+            "namespace-demo-test"
+        );
+        assert_eq!(
+            slug("https://api.example.com/namespace?productkey=demo"),
+            "namespace"
+        );
+    }
+}
@@ -0,0 +1,249 @@
+use chrono::{DateTime, Duration, Utc};
+use regex::Regex;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::types::Error;
+
+#[derive(Debug)]
+pub enum Selector {
+    Equals(String, String),
+    NotEquals(String, String),
+    Exists(String),
+    NotExists(String),
+}
+
+// the characters allowed in label keys (including a prefix) and values
+fn is_label_text(s: &str) -> bool {
+    s.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+}
+
+impl Selector {
+    // parse a comma separated kubernetes-style equality selector, eg.
+    // 'pr-state=closed,team!=core,!pinned'
+    pub fn parse_all(inp: &str) -> Result<Vec<Selector>, Error> {
+        let selectors = inp
+            .split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                let sel = if let Some((k, v)) = s.split_once("!=") {
+                    Selector::NotEquals(k.trim().to_string(), v.trim().to_string())
+                } else if let Some((k, v)) = s.split_once("==").or_else(|| s.split_once('=')) {
+                    Selector::Equals(k.trim().to_string(), v.trim().to_string())
+                } else if let Some(k) = s.strip_prefix('!') {
+                    Selector::NotExists(k.trim().to_string())
+                } else {
+                    Selector::Exists(s.to_string())
+                };
+                let (key, value) = match &sel {
+                    Selector::Equals(k, v) | Selector::NotEquals(k, v) => (k, Some(v)),
+                    Selector::Exists(k) | Selector::NotExists(k) => (k, None),
+                };
+                let invalid = |reason: String| {
+                    Err(Error::Option(
+                        "selector".to_string(),
+                        inp.to_string(),
+                        reason,
+                    ))
+                };
+                if key.is_empty() {
+                    invalid("missing label key".to_string())
+                } else if !is_label_text(key) {
+                    invalid(format!("invalid label key '{}'", key))
+                } else if !value.map_or(true, |v| v.is_empty() || is_label_text(v)) {
+                    invalid(format!("invalid label value '{}'", value.unwrap()))
+                } else {
+                    Ok(sel)
+                }
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        // an empty selector would match, and so delete, every namespace
+        if selectors.is_empty() {
+            return Err(Error::Option(
+                "selector".to_string(),
+                inp.to_string(),
+                "no label selector given".to_string(),
+            ));
+        }
+        Ok(selectors)
+    }
+
+    fn matches(&self, labels: &BTreeMap<String, String>) -> bool {
+        match self {
+            Selector::Equals(k, v) => labels.get(k) == Some(v),
+            Selector::NotEquals(k, v) => labels.get(k) != Some(v),
+            Selector::Exists(k) => labels.contains_key(k),
+            Selector::NotExists(k) => !labels.contains_key(k),
+        }
+    }
+}
+
+// labels may come back either as a map or as a list of key/value pairs
+pub fn labels_of(ns: &Value) -> BTreeMap<String, String> {
+    let as_string = |v: &Value| match v {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    };
+    match ns.get("labels") {
+        Some(Value::Object(map)) => map.iter().map(|(k, v)| (k.clone(), as_string(v))).collect(),
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|item| {
+                let key = item.get("key")?.as_str()?.to_string();
+                Some((key, item.get("value").map(as_string).unwrap_or_default()))
+            })
+            .collect(),
+        _ => BTreeMap::new(),
+    }
+}
+
+pub fn name_of(ns: &Value) -> Option<&str> {
+    ns.get("namespace").and_then(Value::as_str)
+}
+
+fn created_at(ns: &Value) -> Option<DateTime<Utc>> {
+    ["created", "creationTimestamp", "created_at"]
+        .iter()
+        .filter_map(|k| ns.get(*k).and_then(Value::as_str))
+        .filter_map(|t| DateTime::parse_from_rfc3339(t).ok())
+        .map(|t| t.with_timezone(&Utc))
+        .next()
+}
+
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub selectors: Vec<Selector>,
+    pub older_than: Option<Duration>,
+    pub name_regex: Option<Regex>,
+}
+
+impl Filter {
+    pub fn matches(&self, ns: &Value, now: DateTime<Utc>) -> bool {
+        let name = match name_of(ns) {
+            Some(name) => name,
+            None => return false,
+        };
+        if let Some(re) = &self.name_regex {
+            if !re.is_match(name) {
+                return false;
+            }
+        }
+        if let Some(age) = self.older_than {
+            // without a creation time we can't tell, so err on the side of keeping it
+            match created_at(ns) {
+                Some(created) if now - created > age => {}
+                _ => return false,
+            }
+        }
+        let labels = labels_of(ns);
+        self.selectors.iter().all(|s| s.matches(&labels))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2021-06-10T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn filter(selector: &str) -> Filter {
+        Filter {
+            selectors: Selector::parse_all(selector).unwrap(),
+            ..Filter::default()
+        }
+    }
+
+    #[test]
+    fn parse_selectors() {
+        let parsed = Selector::parse_all("pr-state=closed, team!=core,tier==web,pinned,!keep,")
+            .unwrap()
+            .iter()
+            .map(|s| format!("{:?}", s))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            parsed,
+            vec![
+                r#"Equals("pr-state", "closed")"#,
+                r#"NotEquals("team", "core")"#,
+                r#"Equals("tier", "web")"#,
+                r#"Exists("pinned")"#,
+                r#"NotExists("keep")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_selectors() {
+        for selector in &[
+            "=closed",
+            "!",
+            "!=core",
+            "a=b=c",
+            "team name=core",
+            "a=b,c!=d!",
+        ] {
+            match Selector::parse_all(selector) {
+                Err(Error::Option(name, value, _)) => {
+                    assert_eq!(name, "selector");
+                    assert_eq!(value, *selector);
+                }
+                other => panic!("{} parsed as {:?}", selector, other),
+            }
+        }
+    }
+
+    #[test]
+    fn empty_selectors() {
+        for selector in &["", ",", " , ,"] {
+            match Selector::parse_all(selector) {
+                Err(Error::Option(name, _, reason)) => {
+                    assert_eq!(name, "selector");
+                    assert_eq!(reason, "no label selector given");
+                }
+                other => panic!("{:?} parsed as {:?}", selector, other),
+            }
+        }
+    }
+
+    #[test]
+    fn match_labels_in_either_form() {
+        let listed = json!({
+            "namespace": "demo-pr-1",
+            "labels": [{"key": "pr-state", "value": "closed"}, {"key": "pinned", "value": ""}]
+        });
+        let mapped =
+            json!({"namespace": "demo-pr-1", "labels": {"pr-state": "closed", "pinned": ""}});
+        for ns in &[listed, mapped] {
+            assert!(filter("pr-state=closed").matches(ns, now()));
+            assert!(filter("pr-state=closed,pinned").matches(ns, now()));
+            assert!(!filter("pr-state=closed,!pinned").matches(ns, now()));
+            assert!(filter("team!=core").matches(ns, now()));
+            assert!(!filter("pr-state!=closed").matches(ns, now()));
+        }
+    }
+
+    #[test]
+    fn match_age_and_name() {
+        let filter = Filter {
+            older_than: Some(Duration::days(2)),
+            name_regex: Some(Regex::new("^demo-pr-").unwrap()),
+            ..Filter::default()
+        };
+        let old = json!({"namespace": "demo-pr-1", "created": "2021-06-01T00:00:00Z"});
+        let new = json!({"namespace": "demo-pr-2", "creationTimestamp": "2021-06-10T00:00:00Z"});
+        let undated = json!({"namespace": "demo-pr-3"});
+        let other = json!({"namespace": "demo-main", "created_at": "2021-06-01T00:00:00Z"});
+        assert!(filter.matches(&old, now()));
+        assert!(!filter.matches(&new, now()));
+        assert!(!filter.matches(&undated, now()));
+        assert!(!filter.matches(&other, now()));
+        assert!(!filter.matches(&json!({"created": "2021-06-01T00:00:00Z"}), now()));
+    }
+}
@@ -0,0 +1,61 @@
+// @generated from openapi/platform-api.yaml by src/codegen.rs, do not edit.
+// regenerate with `UPDATE_GENERATED=1 cargo test generated`
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Body of a request to create a namespace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NamespaceRequest {
+    /// Product key owning the namespace, which prefixes its name.
+    pub productkey: String,
+    /// How long the namespace lives for, eg. '24h'.
+    pub ttl: String,
+    /// Cluster the namespace is created in.
+    pub cluster: String,
+    /// Name of the namespace, without the product key.
+    pub namespace: String,
+    /// Kubernetes labels for the namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub labels: Option<klap::Labels>,
+    /// Kubernetes annotations for the namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotations: Option<klap::Annotations>,
+    /// Vault access granted to the namespace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vault_config: Option<VaultConfig>,
+    /// Properties beyond those listed in the schema.
+    #[serde(flatten)]
+    pub additional_properties: BTreeMap<String, Value>,
+}
+
+pub const NAMESPACE_REQUEST_FIELDS: &[&str] = &[
+    "productkey",
+    "ttl",
+    "cluster",
+    "namespace",
+    "labels",
+    "annotations",
+    "vault_config",
+];
+
+/// Vault access granted to the namespace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VaultConfig {
+    /// Comma separated service accounts, including 'default'.
+    pub service_account_name: String,
+}
+
+/// Response to creating a namespace.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NamespaceResponse {
+    pub message: String,
+    /// Full name of the namespace.
+    pub namespace: String,
+    /// When the namespace expires, as an RFC 3339 timestamp.
+    pub expiry: String,
+    /// Properties beyond those listed in the schema.
+    #[serde(flatten)]
+    pub additional_properties: BTreeMap<String, Value>,
+}
@@ -0,0 +1,176 @@
+use serde::Deserialize;
+use std::env;
+use std::fs;
+use std::io::BufReader;
+
+use crate::metadata::{sanitize_dns_label, sanitize_label_value, Metadata};
+use crate::types::Error;
+
+pub const EVENT_PATH_ENV_VAR: &str = "GITHUB_EVENT_PATH";
+// namespace names are DNS labels of at most 63 characters
+const MAX_NAMESPACE_LEN: usize = 63;
+
+#[derive(Debug, Deserialize)]
+struct User {
+    login: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Head {
+    #[serde(rename = "ref")]
+    ref_: String,
+    sha: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct PullRequest {
+    number: u64,
+    head: Head,
+    user: User,
+    #[serde(default)]
+    html_url: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Repository {
+    full_name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PullRequestEvent {
+    pub action: String,
+    pull_request: PullRequest,
+    repository: Repository,
+}
+
+pub enum SuffixFrom {
+    Number,
+    Branch,
+}
+
+impl PullRequestEvent {
+    pub fn load(path: Option<&str>) -> Result<Self, Error> {
+        let path = match path {
+            Some(p) => p.to_string(),
+            None => env::var(EVENT_PATH_ENV_VAR).map_err(|e| {
+                Error::Environment(format!(
+                    "Could not get '{}' from environment: {}",
+                    EVENT_PATH_ENV_VAR, e
+                ))
+            })?,
+        };
+        let f = fs::File::open(&path)
+            .map_err(|e| Error::Option("event-path".to_string(), path.clone(), e.to_string()))?;
+        serde_json::from_reader(BufReader::new(f)).map_err(|e| {
+            Error::Option(
+                "event-path".to_string(),
+                path,
+                format!("not a pull_request event: {}", e),
+            )
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.action == "closed"
+    }
+
+    // namespace name suffix, sanitized and short enough that prefixing the
+    // product key still gives a valid namespace name
+    pub fn suffix(&self, productkey: &str, from: SuffixFrom) -> Result<String, Error> {
+        let max = MAX_NAMESPACE_LEN.saturating_sub(productkey.len() + 1);
+        let suffix = match from {
+            SuffixFrom::Number => format!("pr-{}", self.pull_request.number),
+            SuffixFrom::Branch => sanitize_dns_label(&self.pull_request.head.ref_, max),
+        };
+        if suffix.is_empty() || suffix.len() > max {
+            return Err(Error::Unknown(format!(
+                "Could not derive a namespace name for product key '{}' from the pull request",
+                productkey
+            )));
+        }
+        Ok(suffix)
+    }
+
+    pub fn annotate(&self, metadata: &mut Metadata) {
+        let pr = &self.pull_request;
+        let state = if self.is_closed() { "closed" } else { "open" };
+        metadata.labels.extend(vec![
+            ("pr-number".to_string(), pr.number.to_string()),
+            ("pr-state".to_string(), state.to_string()),
+            (
+                "pr-author".to_string(),
+                sanitize_label_value(&pr.user.login),
+            ),
+        ]);
+        let mut annotations = vec![
+            ("github.com/repository", self.repository.full_name.clone()),
+            ("github.com/pr-number", pr.number.to_string()),
+            ("github.com/pr-author", pr.user.login.clone()),
+            ("github.com/pr-branch", pr.head.ref_.clone()),
+            ("github.com/pr-sha", pr.head.sha.clone()),
+        ];
+        if let Some(url) = &pr.html_url {
+            annotations.push(("github.com/pr-url", url.clone()));
+        }
+        for (key, value) in annotations {
+            metadata.annotations.insert(key.to_string(), value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(action: &str, branch: &str) -> PullRequestEvent {
+        serde_json::from_value(json!({
+            "action": action,
+            "number": 1234,
+            "pull_request": {
+                "number": 1234,
+                "head": {"ref": branch, "sha": "abc123"},
+                "user": {"login": "Some[bot]"},
+                "html_url": "https://github.com/org/repo/pull/1234"
+            },
+            "repository": {"full_name": "org/repo"}
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn suffix_from_number_or_branch() {
+        let ev = event("opened", "feature/My_Branch");
+        assert_eq!(ev.suffix("demo", SuffixFrom::Number).unwrap(), "pr-1234");
+        assert_eq!(
+            ev.suffix("demo", SuffixFrom::Branch).unwrap(),
+            "feature-my-branch"
+        );
+    }
+
+    // the product key and '-' leave 63 - 5 characters for the suffix
+    #[test]
+    fn suffix_fits_namespace_name() {
+        let ev = event("opened", &"long-branch-".repeat(10));
+        let suffix = ev.suffix("demo", SuffixFrom::Branch).unwrap();
+        assert!(suffix.len() <= 58);
+        assert!(!suffix.ends_with('-'));
+        assert!(event("opened", "___")
+            .suffix("demo", SuffixFrom::Branch)
+            .is_err());
+        assert!(ev.suffix(&"p".repeat(60), SuffixFrom::Number).is_err());
+    }
+
+    #[test]
+    fn annotations_and_labels() {
+        let mut metadata = Metadata::default();
+        event("closed", "fix").annotate(&mut metadata);
+        assert_eq!(metadata.labels["pr-state"], "closed");
+        assert_eq!(metadata.labels["pr-author"], "Some-bot");
+        assert_eq!(metadata.annotations["github.com/pr-author"], "Some[bot]");
+        assert_eq!(metadata.annotations["github.com/pr-sha"], "abc123");
+        let mut open = Metadata::default();
+        event("synchronize", "fix").annotate(&mut open);
+        assert_eq!(open.labels["pr-state"], "open");
+    }
+}
@@ -0,0 +1,216 @@
+use clap::ArgMatches;
+use log::warn;
+use std::cell::RefCell;
+use std::env;
+
+use crate::types::Error;
+
+// opt-in `${VAR}` / `${VAR-default}` / `${VAR:-default}` substitution of
+// environment variables in files we read. as in the shell, `:-` also uses the
+// default for a variable set to the empty string. `$$` gives a literal `$`. rendered inputs are kept so that
+// dry-runs can show them.
+pub struct Interpolation {
+    enabled: bool,
+    strict: bool,
+    rendered: RefCell<Vec<(String, String)>>,
+}
+
+fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+impl Interpolation {
+    pub fn new(enabled: bool, strict: bool) -> Self {
+        Interpolation {
+            enabled: enabled || strict,
+            strict,
+            rendered: RefCell::new(Vec::new()),
+        }
+    }
+
+    pub fn from_matches(matches: &ArgMatches<'_>) -> Self {
+        Interpolation::new(
+            matches.is_present("interpolate"),
+            matches.is_present("strict-interpolation"),
+        )
+    }
+
+    fn lookup(&self, source: &str, expr: &str) -> Result<String, Error> {
+        let (name, default, default_if_empty) = if let Some((n, d)) = expr.split_once(":-") {
+            (n, Some(d), true)
+        } else if let Some((n, d)) = expr.split_once('-') {
+            (n, Some(d), false)
+        } else {
+            (expr, None, false)
+        };
+        if !is_valid_name(name) {
+            return Err(Error::Interpolation(
+                source.to_string(),
+                format!("invalid variable name in '${{{}}}'", expr),
+            ));
+        }
+        match (env::var(name).ok(), default) {
+            (Some(val), Some(default)) if val.is_empty() && default_if_empty => {
+                Ok(default.to_string())
+            }
+            (Some(val), _) => Ok(val),
+            (None, Some(default)) => Ok(default.to_string()),
+            (None, None) if self.strict => Err(Error::Interpolation(
+                source.to_string(),
+                format!("variable {} is not set", name),
+            )),
+            (None, None) => {
+                warn!(
+                    "{}: variable {} is not set, using empty string",
+                    source, name
+                );
+                Ok(String::new())
+            }
+        }
+    }
+
+    // `source` names where the input came from, for errors and dry-run output
+    pub fn render(&self, source: &str, input: String) -> Result<String, Error> {
+        if !self.enabled {
+            return Ok(input);
+        }
+        let mut out = String::with_capacity(input.len());
+        let mut rest = input.as_str();
+        while let Some(idx) = rest.find('$') {
+            out.push_str(&rest[..idx]);
+            rest = &rest[idx..];
+            if let Some(after) = rest.strip_prefix("$$") {
+                out.push('$');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("${") {
+                let end = after.find('}').ok_or_else(|| {
+                    Error::Interpolation(source.to_string(), "unclosed '${'".to_string())
+                })?;
+                out.push_str(&self.lookup(source, &after[..end])?);
+                rest = &after[end + 1..];
+            } else {
+                out.push('$');
+                rest = &rest[1..];
+            }
+        }
+        out.push_str(rest);
+        self.rendered
+            .borrow_mut()
+            .push((source.to_string(), out.clone()));
+        Ok(out)
+    }
+
+    pub fn rendered(&self) -> Vec<(String, String)> {
+        self.rendered.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // names only used here, so setting them can't affect other tests
+    const SET: &str = "INTERPOLATE_TEST_SET";
+    const UNSET: &str = "INTERPOLATE_TEST_UNSET";
+    const EMPTY: &str = "INTERPOLATE_TEST_EMPTY";
+
+    fn render(interp: &Interpolation, input: &str) -> Result<String, Error> {
+        env::set_var(SET, "value");
+        env::set_var(EMPTY, "");
+        env::remove_var(UNSET);
+        interp.render("spec.yaml", input.to_string())
+    }
+
+    fn message(result: Result<String, Error>) -> String {
+        match result {
+            Err(Error::Interpolation(source, msg)) => {
+                assert_eq!(source, "spec.yaml");
+                msg
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn variables_and_defaults() {
+        let interp = Interpolation::new(true, false);
+        let rendered = render(
+            &interp,
+            "a: ${INTERPOLATE_TEST_SET}\nb: ${INTERPOLATE_TEST_UNSET:-fallback}\nc: ${INTERPOLATE_TEST_SET:-unused}\nd: ${INTERPOLATE_TEST_UNSET}\n",
+        )
+        .unwrap();
+        assert_eq!(rendered, "a: value\nb: fallback\nc: value\nd: \n");
+        assert_eq!(interp.rendered(), vec![("spec.yaml".to_string(), rendered)]);
+    }
+
+    #[test]
+    fn empty_variables() {
+        let interp = Interpolation::new(true, false);
+        assert_eq!(
+            render(
+                &interp,
+                "a: ${INTERPOLATE_TEST_EMPTY}\nb: ${INTERPOLATE_TEST_EMPTY:-fallback}\nc: ${INTERPOLATE_TEST_EMPTY-unused}\nd: ${INTERPOLATE_TEST_UNSET-fallback}\n",
+            )
+            .unwrap(),
+            "a: \nb: fallback\nc: \nd: fallback\n"
+        );
+        let strict = Interpolation::new(false, true);
+        assert_eq!(render(&strict, "${INTERPOLATE_TEST_EMPTY}").unwrap(), "");
+    }
+
+    #[test]
+    fn escapes_and_lone_dollars() {
+        let interp = Interpolation::new(true, false);
+        assert_eq!(
+            render(&interp, "$${INTERPOLATE_TEST_SET} costs $5, $$$$ $").unwrap(),
+            "${INTERPOLATE_TEST_SET} costs $5, $$ $"
+        );
+        assert_eq!(
+            render(&interp, "${INTERPOLATE_TEST_UNSET:-a$b}").unwrap(),
+            "a$b"
+        );
+    }
+
+    #[test]
+    fn malformed_expressions() {
+        let interp = Interpolation::new(true, false);
+        assert_eq!(
+            message(render(&interp, "a: ${INTERPOLATE_TEST_SET")),
+            "unclosed '${'"
+        );
+        assert_eq!(
+            message(render(&interp, "${1BAD}")),
+            "invalid variable name in '${1BAD}'"
+        );
+        assert!(message(render(&interp, "${}")).starts_with("invalid variable name"));
+    }
+
+    #[test]
+    fn strict_mode() {
+        let strict = Interpolation::new(false, true);
+        assert_eq!(
+            message(render(&strict, "${INTERPOLATE_TEST_UNSET}")),
+            format!("variable {} is not set", UNSET)
+        );
+        assert_eq!(
+            render(&strict, "${INTERPOLATE_TEST_UNSET:-ok}").unwrap(),
+            "ok"
+        );
+        assert_eq!(render(&strict, "${INTERPOLATE_TEST_SET}").unwrap(), "value");
+    }
+
+    #[test]
+    fn disabled() {
+        let interp = Interpolation::new(false, false);
+        assert_eq!(
+            render(&interp, "${INTERPOLATE_TEST_SET} $$").unwrap(),
+            "${INTERPOLATE_TEST_SET} $$"
+        );
+        assert!(interp.rendered().is_empty());
+    }
+}
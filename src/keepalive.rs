@@ -0,0 +1,187 @@
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use std::thread;
+
+use crate::types::{Error, NSDef, NSResponse};
+
+// never renew more often than this, even if the API hands back an expiry
+// sooner than `renew_before`
+const MIN_INTERVAL_SECS: i64 = 60;
+const BACKOFF_BASE_SECS: i64 = 30;
+const BACKOFF_MAX_SECS: i64 = 15 * 60;
+
+struct Tracked {
+    name: String,
+    payload: NSDef,
+    next_renewal: DateTime<Utc>,
+    failures: u32,
+    done: bool,
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_expiry(resp: &NSResponse) -> Result<DateTime<Utc>, Error> {
+    DateTime::parse_from_rfc3339(&resp.expiry)
+        .map(|e| e.with_timezone(&Utc))
+        .map_err(|e| {
+            Error::Unknown(format!(
+                "Could not parse expiry '{}' of {}: {}",
+                resp.expiry, resp.namespace, e
+            ))
+        })
+}
+
+fn backoff(failures: u32) -> Duration {
+    let secs = BACKOFF_BASE_SECS.saturating_mul(1 << failures.saturating_sub(1).min(16));
+    Duration::seconds(secs.min(BACKOFF_MAX_SECS))
+}
+
+// keep re-submitting each payload `renew_before` ahead of its expiry until
+// `deadline`, after which the namespaces are left to expire by themselves.
+pub fn run<F>(
+    payloads: Vec<NSDef>,
+    renew_before: Duration,
+    deadline: DateTime<Utc>,
+    mut renew: F,
+) -> Result<(), Error>
+where
+    F: FnMut(&NSDef) -> Result<NSResponse, Error>,
+{
+    let start = Utc::now();
+    let mut tracked: Vec<Tracked> = payloads
+        .into_iter()
+        .map(|payload| Tracked {
+            name: format!("{}-{}", payload.productkey, payload.namespace),
+            payload,
+            next_renewal: start,
+            failures: 0,
+            done: false,
+        })
+        .collect();
+    loop {
+        let now = Utc::now();
+        if now >= deadline {
+            eprintln!(
+                "{} deadline {} reached, no longer renewing",
+                timestamp(now),
+                timestamp(deadline)
+            );
+            return Ok(());
+        }
+        for t in tracked
+            .iter_mut()
+            .filter(|t| !t.done && t.next_renewal <= now)
+        {
+            match renew(&t.payload).and_then(|resp| parse_expiry(&resp)) {
+                Ok(expiry) => {
+                    t.failures = 0;
+                    println!(
+                        "{} renewed {}, expires {}",
+                        timestamp(now),
+                        t.name,
+                        timestamp(expiry)
+                    );
+                    if expiry >= deadline {
+                        println!(
+                            "{} {} now outlives the deadline, no longer renewing",
+                            timestamp(now),
+                            t.name
+                        );
+                        t.done = true;
+                    } else {
+                        t.next_renewal =
+                            (expiry - renew_before).max(now + Duration::seconds(MIN_INTERVAL_SECS));
+                    }
+                }
+                Err(e) => {
+                    t.failures += 1;
+                    let wait = backoff(t.failures);
+                    t.next_renewal = now + wait;
+                    eprintln!(
+                        "{} failed to renew {} (attempt {}), retrying in {}s: {}",
+                        timestamp(now),
+                        t.name,
+                        t.failures,
+                        wait.num_seconds(),
+                        e
+                    );
+                }
+            }
+        }
+        let next = match tracked
+            .iter()
+            .filter(|t| !t.done)
+            .map(|t| t.next_renewal)
+            .min()
+        {
+            Some(next) => next.min(deadline),
+            None => return Ok(()),
+        };
+        if let Ok(sleep) = (next - Utc::now()).to_std() {
+            thread::sleep(sleep);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::NSDefBuilder;
+
+    fn payload() -> NSDef {
+        NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("1h")
+            .cluster("cluster")
+            .namespace("app")
+            .build()
+            .unwrap()
+    }
+
+    fn response(expiry: &str) -> NSResponse {
+        NSResponse {
+            message: "renewed".to_string(),
+            namespace: "demo-app".to_string(),
+            expiry: expiry.to_string(),
+            additional_properties: Default::default(),
+        }
+    }
+
+    #[test]
+    fn backoff_schedule() {
+        let schedule: Vec<i64> = (1..=8).map(|n| backoff(n).num_seconds()).collect();
+        assert_eq!(schedule, vec![30, 60, 120, 240, 480, 900, 900, 900]);
+        assert_eq!(backoff(u32::MAX).num_seconds(), BACKOFF_MAX_SECS);
+    }
+
+    #[test]
+    fn stops_once_expiry_outlives_deadline() {
+        let deadline = Utc::now() + Duration::hours(1);
+        let expiry = timestamp(deadline + Duration::minutes(5));
+        let mut calls = 0;
+        run(vec![payload()], Duration::minutes(10), deadline, |_| {
+            calls += 1;
+            Ok(response(&expiry))
+        })
+        .unwrap();
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn nothing_renewed_after_deadline() {
+        let deadline = Utc::now() - Duration::seconds(1);
+        run(vec![payload()], Duration::minutes(10), deadline, |_| {
+            panic!("renewed after the deadline")
+        })
+        .unwrap();
+    }
+
+    #[test]
+    fn expiry_must_parse() {
+        match parse_expiry(&response("tomorrow")) {
+            Err(Error::Unknown(msg)) => assert!(msg.contains("'tomorrow' of demo-app")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+use crate::types::Error;
+
+// kubeconfig structures only model the fields we use, everything else is
+// kept in `other` so the file can be written back without losing entries.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Cluster {
+    pub server: String,
+    #[serde(
+        rename = "certificate-authority-data",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub certificate_authority_data: Option<String>,
+    #[serde(
+        rename = "certificate-authority",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub certificate_authority: Option<String>,
+    #[serde(
+        rename = "insecure-skip-tls-verify",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub insecure_skip_tls_verify: Option<bool>,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedCluster {
+    pub name: String,
+    pub cluster: Cluster,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct AuthInfo {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub token: Option<String>,
+    #[serde(rename = "tokenFile", skip_serializing_if = "Option::is_none", default)]
+    pub token_file: Option<String>,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedAuthInfo {
+    pub name: String,
+    #[serde(default)]
+    pub user: AuthInfo,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Context {
+    pub cluster: String,
+    pub user: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub namespace: Option<String>,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NamedContext {
+    pub name: String,
+    pub context: Context,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Kubeconfig {
+    #[serde(
+        rename = "apiVersion",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub api_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub clusters: Vec<NamedCluster>,
+    #[serde(default)]
+    pub users: Vec<NamedAuthInfo>,
+    #[serde(default)]
+    pub contexts: Vec<NamedContext>,
+    #[serde(
+        rename = "current-context",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub current_context: Option<String>,
+    #[serde(flatten)]
+    pub other: BTreeMap<String, Value>,
+}
+
+// explicit path, otherwise the first entry of KUBECONFIG, otherwise ~/.kube/config
+pub fn kubeconfig_path(explicit: Option<&str>) -> Result<PathBuf, Error> {
+    if let Some(path) = explicit {
+        return Ok(PathBuf::from(path));
+    }
+    if let Ok(paths) = env::var("KUBECONFIG") {
+        if let Some(path) = env::split_paths(&paths).next() {
+            return Ok(path);
+        }
+    }
+    env::var("HOME")
+        .map(|home| PathBuf::from(home).join(".kube").join("config"))
+        .map_err(|e| Error::Environment(format!("Could not locate kubeconfig: {}", e)))
+}
+
+impl Kubeconfig {
+    pub fn load(path: &PathBuf) -> Result<Self, Error> {
+        let f = fs::File::open(path).map_err(|e| Error::Kubeconfig(path.clone(), e.to_string()))?;
+        serde_yaml::from_reader(BufReader::new(f))
+            .map_err(|e| Error::Kubeconfig(path.clone(), e.to_string()))
+    }
+
+    // write to a temporary file alongside the original and rename it into
+    // place, so a failure can't leave a truncated kubeconfig behind
+    pub fn save(&self, path: &PathBuf) -> Result<(), Error> {
+        let contents = serde_yaml::to_string(self)
+            .map_err(|e| Error::Kubeconfig(path.clone(), e.to_string()))?;
+        let mut tmp = path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::write(&tmp, contents)
+            .and_then(|_| match fs::metadata(path) {
+                Ok(m) => fs::set_permissions(&tmp, m.permissions()),
+                Err(_) => Ok(()),
+            })
+            .and_then(|_| fs::rename(&tmp, path))
+            .map_err(|e| Error::Kubeconfig(path.clone(), e.to_string()))
+    }
+
+    fn context_index(&self, name: Option<&str>) -> Result<usize, Error> {
+        let name = match name.or(self.current_context.as_deref()) {
+            Some(n) => n,
+            None => {
+                return Err(Error::Unknown(
+                    "no context given and kubeconfig has no current-context".to_string(),
+                ))
+            }
+        };
+        self.contexts
+            .iter()
+            .position(|c| c.name == name)
+            .ok_or_else(|| Error::Unknown(format!("context '{}' not found in kubeconfig", name)))
+    }
+
+    pub fn context(&self, name: Option<&str>) -> Result<&NamedContext, Error> {
+        self.context_index(name).map(|idx| &self.contexts[idx])
+    }
+
+    // add (or replace) a context named `name`, copying the cluster and user of
+    // the `base` context but pointing at `namespace`
+    pub fn add_namespace_context(
+        &mut self,
+        base: Option<&str>,
+        name: &str,
+        namespace: &str,
+    ) -> Result<(), Error> {
+        let mut context = self.context(base)?.context.clone();
+        context.namespace = Some(namespace.to_string());
+        let entry = NamedContext {
+            name: name.to_string(),
+            context,
+            other: BTreeMap::new(),
+        };
+        match self.contexts.iter().position(|c| c.name == name) {
+            Some(idx) => self.contexts[idx] = entry,
+            None => self.contexts.push(entry),
+        }
+        Ok(())
+    }
+
+    pub fn set_namespace(&mut self, context: Option<&str>, namespace: &str) -> Result<(), Error> {
+        let idx = self.context_index(context)?;
+        self.contexts[idx].context.namespace = Some(namespace.to_string());
+        Ok(())
+    }
+
+    pub fn cluster(&self, name: &str) -> Result<&Cluster, Error> {
+        self.clusters
+            .iter()
+            .find(|c| c.name == name)
+            .map(|c| &c.cluster)
+            .ok_or_else(|| Error::Unknown(format!("cluster '{}' not found in kubeconfig", name)))
+    }
+
+    pub fn user(&self, name: &str) -> Result<&AuthInfo, Error> {
+        self.users
+            .iter()
+            .find(|u| u.name == name)
+            .map(|u| &u.user)
+            .ok_or_else(|| Error::Unknown(format!("user '{}' not found in kubeconfig", name)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KUBECONFIG: &str = r#"
+apiVersion: v1
+kind: Config
+preferences:
+  colors: true
+clusters:
+  - name: dev
+    cluster:
+      server: https://dev.example.com
+      proxy-url: http://proxy:3128
+    extensions:
+      - name: cluster-ext
+  - name: prod
+    cluster:
+      server: https://prod.example.com
+users:
+  - name: dev-user
+    user:
+      exec:
+        command: kubelogin
+    extensions:
+      - name: user-ext
+contexts:
+  - name: dev
+    context:
+      cluster: dev
+      user: dev-user
+      namespace: default
+    extensions:
+      - name: context-ext
+  - name: prod
+    context:
+      cluster: prod
+      user: dev-user
+current-context: dev
+"#;
+
+    fn config_file(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("kubeconfig-{}-{}", name, std::process::id()));
+        fs::write(&path, KUBECONFIG).unwrap();
+        path
+    }
+
+    #[test]
+    fn unknown_entries_survive_save() {
+        let path = config_file("roundtrip");
+        let mut config = Kubeconfig::load(&path).unwrap();
+        config
+            .add_namespace_context(None, "demo-app", "demo-app")
+            .unwrap();
+        config.save(&path).unwrap();
+
+        let saved: Value = serde_yaml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(saved["preferences"]["colors"], Value::Bool(true));
+        let cluster = &saved["clusters"][0];
+        assert_eq!(cluster["cluster"]["proxy-url"], "http://proxy:3128");
+        assert_eq!(cluster["extensions"][0]["name"], "cluster-ext");
+        let user = &saved["users"][0];
+        assert_eq!(user["user"]["exec"]["command"], "kubelogin");
+        assert_eq!(user["extensions"][0]["name"], "user-ext");
+        assert_eq!(saved["contexts"][0]["extensions"][0]["name"], "context-ext");
+        let added = &saved["contexts"][2];
+        assert_eq!(added["name"], "demo-app");
+        assert_eq!(added["context"]["cluster"], "dev");
+        assert_eq!(added["context"]["namespace"], "demo-app");
+        assert_eq!(saved["current-context"], "dev");
+    }
+
+    #[test]
+    fn context_replaced_by_name() {
+        let mut config: Kubeconfig = serde_yaml::from_str(KUBECONFIG).unwrap();
+        config
+            .add_namespace_context(Some("dev"), "demo-app", "first")
+            .unwrap();
+        config
+            .add_namespace_context(Some("prod"), "demo-app", "second")
+            .unwrap();
+        let names: Vec<&str> = config.contexts.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, vec!["dev", "prod", "demo-app"]);
+        let replaced = &config.context(Some("demo-app")).unwrap().context;
+        assert_eq!(replaced.cluster, "prod");
+        assert_eq!(replaced.namespace.as_deref(), Some("second"));
+    }
+}
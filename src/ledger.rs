@@ -0,0 +1,239 @@
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+
+use crate::provenance::ci_job_url;
+use crate::types::{Error, NSDef, NSResponse};
+
+const LEDGER_ENV_VAR: &str = "PLATFORM_API_LEDGER";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Action {
+    Created,
+    Deleted,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LedgerEntry {
+    pub timestamp: DateTime<Utc>,
+    pub action: Action,
+    pub namespace: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub productkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub cluster: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub expiry: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub payload_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub host: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub ci_job: Option<String>,
+}
+
+impl LedgerEntry {
+    fn new(action: Action, namespace: &str) -> Self {
+        LedgerEntry {
+            timestamp: Utc::now(),
+            action,
+            namespace: namespace.to_string(),
+            productkey: None,
+            cluster: None,
+            expiry: None,
+            payload_hash: None,
+            user: env::var("USER").or_else(|_| env::var("USERNAME")).ok(),
+            host: env::var("HOSTNAME")
+                .ok()
+                .or_else(|| fs::read_to_string("/etc/hostname").ok())
+                .map(|h| h.trim().to_string()),
+            ci_job: ci_job_url(),
+        }
+    }
+
+    pub fn created(payload: &NSDef, resp: &NSResponse) -> Self {
+        let mut entry = LedgerEntry::new(Action::Created, &resp.namespace);
+        entry.productkey = Some(payload.productkey.clone());
+        entry.cluster = Some(payload.cluster.clone());
+        entry.expiry = Some(resp.expiry.clone());
+        entry.payload_hash = serde_json::to_vec(payload)
+            .ok()
+            .map(|body| format!("{:x}", Sha256::digest(&body)));
+        entry
+    }
+
+    pub fn deleted(namespace: &str) -> Self {
+        LedgerEntry::new(Action::Deleted, namespace)
+    }
+
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        match self.expiry.as_deref().map(DateTime::parse_from_rfc3339) {
+            Some(Ok(expiry)) => expiry < now,
+            _ => false,
+        }
+    }
+}
+
+pub fn ledger_path() -> Result<PathBuf, Error> {
+    if let Ok(path) = env::var(LEDGER_ENV_VAR) {
+        return Ok(PathBuf::from(path));
+    }
+    let data_dir = match env::var("XDG_DATA_HOME") {
+        Ok(dir) => PathBuf::from(dir),
+        Err(_) => env::var("HOME")
+            .map(|home| PathBuf::from(home).join(".local").join("share"))
+            .map_err(|e| {
+                Error::Environment(format!(
+                    "Could not locate ledger, set {}: {}",
+                    LEDGER_ENV_VAR, e
+                ))
+            })?,
+    };
+    Ok(data_dir.join("platformapi-namespace").join("ledger.jsonl"))
+}
+
+fn append(entry: &LedgerEntry) -> Result<(), Error> {
+    let path = ledger_path()?;
+    let err = |e: std::io::Error| Error::Ledger(path.clone(), e.to_string());
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(err)?;
+    }
+    let mut line = serde_json::to_string(entry).unwrap();
+    line.push('\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(line.as_bytes()))
+        .map_err(err)
+}
+
+// recording is best-effort, a broken ledger shouldn't fail the command
+pub fn record(entry: LedgerEntry) {
+    if let Err(e) = append(&entry) {
+        warn!("could not record {} in ledger: {}", entry.namespace, e);
+    }
+}
+
+pub fn read_entries() -> Result<Vec<LedgerEntry>, Error> {
+    let path = ledger_path()?;
+    let f = match fs::File::open(&path) {
+        Ok(f) => f,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(Error::Ledger(path, e.to_string())),
+    };
+    let mut entries = Vec::new();
+    for (idx, line) in BufReader::new(f).lines().enumerate() {
+        let line = line.map_err(|e| Error::Ledger(path.clone(), e.to_string()))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| {
+            Error::Ledger(
+                path.clone(),
+                format!("invalid entry on line {}: {}", idx + 1, e),
+            )
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+// the most recent creation of each namespace that hasn't since been deleted
+// or expired
+pub fn active(entries: Vec<LedgerEntry>, now: DateTime<Utc>) -> Vec<LedgerEntry> {
+    let mut latest: HashMap<String, LedgerEntry> = HashMap::new();
+    for entry in entries {
+        latest.insert(entry.namespace.clone(), entry);
+    }
+    let mut active: Vec<LedgerEntry> = latest
+        .into_values()
+        .filter(|e| e.action == Action::Created && !e.is_expired(now))
+        .collect();
+    active.sort_by_key(|e| e.timestamp);
+    active
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn at(minutes: i64) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2021-06-10T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+            + Duration::minutes(minutes)
+    }
+
+    fn entry(minutes: i64, action: Action, namespace: &str, expires: i64) -> LedgerEntry {
+        let mut entry = LedgerEntry::new(action, namespace);
+        entry.timestamp = at(minutes);
+        if action == Action::Created {
+            entry.expiry = Some(at(expires).to_rfc3339());
+        }
+        entry
+    }
+
+    fn names(entries: &[LedgerEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.namespace.as_str()).collect()
+    }
+
+    #[test]
+    fn active_folds_to_latest_entry() {
+        let entries = vec![
+            entry(0, Action::Created, "demo-deleted", 600),
+            entry(1, Action::Created, "demo-recreated", 600),
+            entry(2, Action::Created, "demo-expired", 30),
+            entry(3, Action::Created, "demo-renewed", 30),
+            entry(4, Action::Deleted, "demo-deleted", 0),
+            entry(5, Action::Deleted, "demo-recreated", 0),
+            entry(6, Action::Created, "demo-renewed", 600),
+            entry(7, Action::Created, "demo-recreated", 600),
+            entry(8, Action::Created, "demo-live", 600),
+        ];
+        let active = active(entries, at(60));
+        assert_eq!(
+            names(&active),
+            vec!["demo-renewed", "demo-recreated", "demo-live"]
+        );
+        assert_eq!(active[1].timestamp, at(7));
+    }
+
+    #[test]
+    fn expiry() {
+        let created = entry(0, Action::Created, "demo-app", 30);
+        assert!(!created.is_expired(at(29)));
+        assert!(created.is_expired(at(31)));
+        let mut unparseable = created.clone();
+        unparseable.expiry = Some("soon".to_string());
+        assert!(!unparseable.is_expired(at(31)));
+        assert!(!entry(0, Action::Deleted, "demo-app", 0).is_expired(at(31)));
+    }
+
+    // optional fields are left out, and older entries without them still load
+    #[test]
+    fn entry_format() {
+        let mut deleted = entry(0, Action::Deleted, "demo-app", 0);
+        deleted.user = None;
+        deleted.host = None;
+        deleted.ci_job = None;
+        let line = serde_json::to_string(&deleted).unwrap();
+        assert_eq!(
+            line,
+            r#"{"timestamp":"2021-06-10T12:00:00Z","action":"deleted","namespace":"demo-app"}"#
+        );
+        let parsed: LedgerEntry = serde_json::from_str(&line).unwrap();
+        assert_eq!(parsed.action, Action::Deleted);
+        assert_eq!(parsed.productkey, None);
+    }
+}
@@ -1,21 +1,67 @@
-use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::ArgMatches;
 use core::time::Duration;
 use klap::{Annotations, Labels};
-use log::info;
 use regex::Regex;
-use reqwest::blocking::Client;
-use std::collections::HashMap;
 use std::env;
-use std::io::BufReader;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
 
+mod api;
 mod auth;
+mod capabilities;
+mod cli;
+#[cfg(test)]
+mod codegen;
+mod config;
+mod dryrun;
+mod exec;
+mod extra;
+mod fixtures;
+mod gc;
+mod generated;
+mod github;
+mod interpolate;
+mod keepalive;
+mod kubeconfig;
+mod ledger;
 mod metadata;
+mod mock;
+mod plan;
+mod pool;
+mod provenance;
+mod schema;
+mod secret;
+mod spec;
+mod transport;
 mod types;
+mod wait;
+use api::BaseUrl;
 use auth::get_bearer_token;
-use metadata::metadata_from_matches;
-use types::{Error, ExitError, ExtraProps, NSDef, NSDefBuilder, NSResponse, VaultServiceAccounts};
+use capabilities::{Cache, Capabilities};
+use cli::{app, parse_duration};
+use config::Config;
+use dryrun::{DryRun, Setting};
+use exec::run_with_cleanup;
+use extra::extra_from_matches;
+use fixtures::{RecordingTransport, ReplayTransport};
+use gc::{Filter, Selector};
+use github::{PullRequestEvent, SuffixFrom};
+use interpolate::Interpolation;
+use kubeconfig::{kubeconfig_path, Kubeconfig};
+use ledger::{Action, LedgerEntry};
+use metadata::{metadata_from_matches, Metadata};
+use mock::Fault;
+use pool::run_concurrently;
+use schema::{schema, Kind};
+use spec::read_specs;
+use transport::{ReqwestTransport, Transport};
+use types::{Error, ExitError, NSDef, NSDefBuilder, NSResponse, Token, VaultServiceAccounts};
+use wait::{KubeClient, Readiness};
 
 const HOSTNAME_ENV_VAR: &str = "PLATFORM_API_HOSTNAME";
+const BASE_URL_ENV_VAR: &str = "PLATFORM_API_BASE_URL";
 const CLUSTER_ENV_VAR: &str = "PLATFORM_API_CLUSTER";
 const TENANT_ENV_VAR: &str = "PLATFORM_API_TENANT";
 
@@ -38,15 +84,6 @@ macro_rules! option_or_env {
     };
 }
 
-fn validate_ttl(inp: String) -> Result<(), String> {
-    let re = Regex::new(r"^(1([hd]|[0-9]h)|2([hd]|[0-4]h)|[3-7][hd]|[89]h)$").unwrap();
-    if re.is_match(&inp) {
-        Ok(())
-    } else {
-        Err(String::from("Valid TTLs are 1-24h or 1-7d"))
-    }
-}
-
 fn match_vault_service_accounts(matches: &ArgMatches<'_>) -> VaultServiceAccounts {
     let mut vsas: VaultServiceAccounts;
     if let Some(val) = matches.value_of("svcac-raw") {
@@ -61,234 +98,701 @@ fn match_vault_service_accounts(matches: &ArgMatches<'_>) -> VaultServiceAccount
     vsas
 }
 
-fn match_extra(matches: &ArgMatches<'_>) -> Result<ExtraProps, Error> {
-    if let Some(val) = matches.value_of("extra-props") {
-        if let Some(filename) = val.strip_prefix('@') {
-            let f = std::fs::File::open(filename).map_err(|e| {
-                Error::Option("extra-data".to_string(), val.to_string(), e.to_string())
-            })?;
-            serde_yaml::from_reader(BufReader::new(f)).map_err(|e| {
-                Error::Option("extra-data".to_string(), val.to_string(), e.to_string())
-            })
-        } else {
-            serde_yaml::from_str(val).map_err(|e| {
-                Error::Option("extra-data".to_string(), val.to_string(), e.to_string())
-            })
+fn main() -> Result<(), ExitError> {
+    env_logger::init();
+    let matches = app().get_matches();
+    // set before dispatching so dry-runs, which never build a transport,
+    // hide the configured keys too
+    if let (_, Some(submatch)) = matches.subcommand() {
+        secret::set_redact_keys(Config::from_matches(submatch)?.redact_keys);
+    }
+    match matches.subcommand() {
+        ("create", Some(crmatch)) => run_create(crmatch)?,
+        ("exec", Some(exmatch)) => run_exec(exmatch)?,
+        ("plan", Some(plmatch)) => run_plan(plmatch)?,
+        ("pr", Some(prmatch)) => run_pr(prmatch)?,
+        ("gc", Some(gcmatch)) => run_gc(gcmatch)?,
+        ("history", Some(himatch)) => run_history(himatch)?,
+        ("keepalive", Some(kamatch)) => run_keepalive(kamatch)?,
+        ("apply", Some(apmatch)) => run_apply(apmatch)?,
+        ("mock-server", Some(mkmatch)) => run_mock_server(mkmatch)?,
+        ("schema", Some(scmatch)) => run_schema(scmatch),
+        _ => panic!("No subcommand"),
+    }
+    Ok(())
+}
+
+fn payload_from_matches(
+    crmatch: &ArgMatches<'_>,
+    cluster: String,
+    interp: &Interpolation,
+) -> Result<NSDef, Error> {
+    let productkey = crmatch.value_of("productkey").unwrap();
+    let mut name = crmatch.value_of("name").unwrap().to_string();
+    let mut metadata = metadata_from_matches(crmatch, interp)?;
+    let mut strict_strip_prefix = false;
+    if name == "-" {
+        name = match metadata.name.take() {
+            Some(mname) => mname,
+            None => {
+                return Err(Error::Unknown(
+                    "name passed as '-' but no name provided in manifest metadata".to_string(),
+                ));
+            }
+        };
+        strict_strip_prefix = true;
+    }
+    if crmatch.is_present("strip-prefix") || strict_strip_prefix {
+        if let Some(suffix) = name.strip_prefix(&format!("{}-", productkey)) {
+            name = suffix.to_string();
+        } else if strict_strip_prefix {
+            return Err(Error::Unknown(format!(
+                "Expected that name '{}' is prefixed with product key '{}'",
+                name, productkey
+            )));
         }
+    }
+    build_payload(crmatch, productkey, name, metadata, cluster, interp)
+}
+
+fn build_payload(
+    matches: &ArgMatches<'_>,
+    productkey: &str,
+    name: String,
+    metadata: Metadata,
+    cluster: String,
+    interp: &Interpolation,
+) -> Result<NSDef, Error> {
+    let ttl = matches.value_of("ttl").unwrap();
+    let vsas = match_vault_service_accounts(matches);
+    let extra = extra_from_matches(matches, interp)?;
+    let labelscollected: Labels = metadata.labels.into_iter().map(|a| a.into()).collect();
+    let annotationscollected: Annotations =
+        metadata.annotations.into_iter().map(|a| a.into()).collect();
+    let payload = NSDefBuilder::default()
+        .productkey(productkey)
+        .ttl(ttl)
+        .cluster(cluster)
+        .namespace(name)
+        .labels(labelscollected)
+        .annotations(annotationscollected)
+        .vault_service_accounts(vsas)
+        .extra_properties(extra)
+        .build()
+        .unwrap();
+    payload.check_overrides(matches.is_present("allow-override"))?;
+    Ok(payload)
+}
+
+// --base-url and PLATFORM_API_BASE_URL take precedence over the original
+// --hostname and PLATFORM_API_HOSTNAME
+// options beat env vars, so the order is --base-url, --hostname,
+// PLATFORM_API_BASE_URL then PLATFORM_API_HOSTNAME
+fn base_url_setting(matches: &ArgMatches<'_>) -> (&'static str, Setting) {
+    let base = Setting::resolve(matches, "base-url", BASE_URL_ENV_VAR);
+    if base.value.is_some() && !matches.is_present("hostname") {
+        ("base-url", base)
     } else {
-        Ok(HashMap::new())
+        (
+            "hostname",
+            Setting::resolve(matches, "hostname", HOSTNAME_ENV_VAR),
+        )
     }
 }
 
-fn create(hostname: &str, tenant: &str, payload: NSDef) -> Result<NSResponse, Error> {
-    let client = Client::new();
-    let token = get_bearer_token(&client, tenant)?;
-    let url = format!("https://{}/namespace", hostname);
-    info!(
-        "submitting request body to {}: {}",
-        url,
-        serde_json::to_string(&payload).unwrap_or_else(|err| format!("error: {:?}", err))
-    );
-    let timeout: Duration = match env::var("PLATFORM_API_TIMEOUT_SECS") {
-        Ok(val) => val
-            .parse()
-            .map(Duration::from_secs)
-            .expect("Could not parse PLATFORM_API_TIMEOUT_SECS as integer"),
-        Err(_) => Duration::from_secs(90),
-    };
-    let res = client
-        .post(&url)
-        .bearer_auth(token)
-        .json(&payload)
-        .timeout(timeout)
-        .send();
-    let resp = match res {
-        Ok(r) => r,
-        Err(e) => {
-            if e.is_timeout() {
-                return Err(Error::APITimeout);
-            } else {
-                return Err(Error::Unknown(format!(
-                    "Got an unknown error communicating with the Platform API: {}",
-                    e
-                )));
-            }
+fn base_url_from_matches(matches: &ArgMatches<'_>) -> Result<BaseUrl, Error> {
+    match base_url_setting(matches).1.value {
+        Some(url) => BaseUrl::parse(&url),
+        None => {
+            eprintln!(
+                "'--base-url' and '--hostname' options missing and neither {} nor {} env var set",
+                BASE_URL_ENV_VAR, HOSTNAME_ENV_VAR
+            );
+            std::process::exit(1);
         }
-    };
-    let status = resp.status();
-    let rtext = resp.text().unwrap();
-    if status.is_success() {
-        let resp = serde_json::from_str(&rtext)
-            .map_err(|e| Error::Unknown(format!("Error decoding API Response: {}", e)))?;
-        Ok(resp)
+    }
+}
+
+fn dry_run_url(base: &Setting) -> String {
+    match BaseUrl::parse(&base.value_or_placeholder()) {
+        Ok(base) => base.namespace_url(),
+        Err(e) => format!("<{}>", e),
+    }
+}
+
+// probe what the API supports and pick the endpoint version to use
+fn negotiate(
+    matches: &ArgMatches<'_>,
+    transport: &dyn Transport,
+    base: BaseUrl,
+    token: &Token,
+) -> Result<(BaseUrl, Capabilities), Error> {
+    if matches.is_present("no-probe") {
+        return Ok((base, Capabilities::default()));
+    }
+    let cache = Cache::select(
+        matches.is_present("record") || matches.is_present("replay"),
+        matches.is_present("refresh-capabilities"),
+    );
+    let caps = Capabilities::probe(transport, &base, token, cache);
+    Ok((caps.negotiate(&base)?, caps))
+}
+
+fn fail_unsupported(matches: &ArgMatches<'_>) -> bool {
+    matches.value_of("unsupported-fields") == Some("fail")
+}
+
+// the network, optionally recording every exchange, or recorded fixtures
+fn transport_from_matches(matches: &ArgMatches<'_>) -> Result<Box<dyn Transport>, Error> {
+    let config = Config::from_matches(matches)?;
+    if let Some(dir) = matches.value_of("replay") {
+        return Ok(Box::new(ReplayTransport::new(Path::new(dir))?));
+    }
+    let transport = ReqwestTransport::from_config(&config.http)?;
+    if let Some(dir) = matches.value_of("record") {
+        Ok(Box::new(RecordingTransport::new(
+            transport,
+            Path::new(dir),
+        )?))
     } else {
-        Err(Error::Api(status.as_u16(), rtext))
+        Ok(Box::new(transport))
     }
 }
 
-fn main() -> Result<(), ExitError> {
-    env_logger::init();
-    let matches = App::new("Platform API Namespace Client")
-        .version(env!("CARGO_PKG_VERSION"))
-        .setting(AppSettings::SubcommandRequiredElseHelp)
-        .subcommand(
-            SubCommand::with_name("create")
-                .about("Create Dynamic Namespace")
-                .arg(
-                    Arg::with_name("ttl")
-                        .long("ttl")
-                        .help("ttl for namespace. valid values are 1-24h or 1-7d")
-                        .validator(validate_ttl)
-                        .default_value("24h")
-                        .takes_value(true)
-                        .required(false),
-                )
-                .arg(
-                    Arg::with_name("strip-prefix")
-                        .long("strip-prefix")
-                        .help("strip prefix from namespace name if it is already prepended")
-                        .short("s")
-                        .takes_value(false)
-                        .required(false),
-                )
-                .arg(
-                    Arg::with_name("labels")
-                        .short("l")
-                        .long("labels")
-                        .required(false)
-                        .takes_value(true)
-                        .multiple(true)
-                        .number_of_values(1),
-                )
-                .arg(
-                    Arg::with_name("annotations")
-                        .short("a")
-                        .long("annotation")
-                        .required(false)
-                        .takes_value(true)
-                        .multiple(true)
-                        .number_of_values(1)
-                )
-                .arg(
-                    Arg::with_name("manifest")
-                        .long("metadata-from-manifest")
-                        .required_if("name", "-")
-                        .takes_value(true)
-                        .multiple(false)
-                        .number_of_values(1)
-                )
-                .arg(
-                    Arg::with_name("svcac")
-                        .long("vault-service-account")
-                        .help("add an additional service account for vault access")
-                        .required(false)
-                        .takes_value(true)
-                        .multiple(true)
-                        .number_of_values(1),
-                )
-                .arg(
-                    Arg::with_name("svcac-raw")
-                        .long("vault-service-account-raw")
-                        .help("service accounts for vault access. comma-separated raw list of values.")
-                        .required(false)
-                        .takes_value(true)
-                        .multiple(false)
-                        .conflicts_with("svcac")
-                        .number_of_values(1),
-                )
-                .arg(
-                    Arg::with_name("extra-props")
-                        .long("extra-data")
-                        .help("provide extra params to api by reading in yaml/json. value prefixed with '@' is treated as a filename.")
-                        .takes_value(true)
-                        .required(false)
-                        .multiple(false)
-                        .number_of_values(1),
-                )
-                .arg(
-                    Arg::with_name("debug")
-                        .short("d")
-                        .long("dry-run")
-                        .takes_value(false)
-                        .required(false)
-                )
-                .arg(Arg::with_name("hostname").long("hostname").required(false).takes_value(true).help("hostname of API, otherwise read from PLATFORM_API_HOSTNAME env var"))
-                .arg(Arg::with_name("cluster").long("cluster").required(false).takes_value(true).help("cluster name, otherwise read from PLATFORM_API_CLUSTER env var"))
-                .arg(Arg::with_name("tenant").long("tenant").required(false).takes_value(true).help("tenant info for auth, otherwise read from PLATFORM_API_TENANT env var"))
-                .arg(Arg::with_name("productkey").required(true).index(1).help("product key, prepended to namespace name"))
-                .arg(Arg::with_name("name").required(true).index(2).help("namespace name, appended as suffix to product key")),
-        )
-        .get_matches();
-    if let Some(crmatch) = matches.subcommand_matches("create") {
-        let productkey = crmatch.value_of("productkey").unwrap();
-        let mut name = crmatch.value_of("name").unwrap().to_string();
-        let ttl = crmatch.value_of("ttl").unwrap();
-        let metadata = metadata_from_matches(crmatch)?;
-        let mut strict_strip_prefix = false;
-        if name == "-" {
-            name = match metadata.name {
-                Some(mname) => mname,
-                None => {
-                    return Err(Error::Unknown(
-                        "name passed as '-' but no name provided in manifest metadata".to_string(),
-                    )
-                    .into());
-                }
-            };
-            strict_strip_prefix = true;
+fn print_response(matches: &ArgMatches<'_>, resp: &NSResponse) {
+    match matches.value_of("output") {
+        Some("json") => println!("{}", serde_json::to_string_pretty(resp).unwrap()),
+        Some("yaml") => print!("{}", serde_yaml::to_string(resp).unwrap()),
+        _ => println!("{}", resp),
+    }
+}
+
+fn run_create(crmatch: &ArgMatches<'_>) -> Result<(), Error> {
+    if crmatch.occurrences_of("debug") > 0 {
+        let (base_name, base) = base_url_setting(crmatch);
+        let cluster = Setting::resolve(crmatch, "cluster", CLUSTER_ENV_VAR);
+        let tenant = Setting::resolve(crmatch, "tenant", TENANT_ENV_VAR);
+        let interp = Interpolation::from_matches(crmatch);
+        let payload = payload_from_matches(crmatch, cluster.value_or_placeholder(), &interp)?;
+        DryRun {
+            settings: vec![
+                (base_name, &base),
+                ("cluster", &cluster),
+                ("tenant", &tenant),
+            ],
+            method: "POST",
+            url: dry_run_url(&base),
+            rendered: interp.rendered(),
         }
-        if crmatch.is_present("strip-prefix") || strict_strip_prefix {
-            if let Some(suffix) = name.strip_prefix(&format!("{}-", productkey)) {
-                name = suffix.to_string();
-            } else if strict_strip_prefix {
-                return Err(Error::Unknown(format!(
-                    "Expected that name '{}' is prefixed with product key '{}'",
-                    name, productkey
-                ))
-                .into());
+        .report(&payload);
+        return Ok(());
+    }
+    let cluster: String = option_or_env!(crmatch, "cluster", CLUSTER_ENV_VAR);
+    let payload = payload_from_matches(crmatch, cluster, &Interpolation::from_matches(crmatch))?;
+    let base = base_url_from_matches(crmatch)?;
+    let tenant: String = option_or_env!(crmatch, "tenant", TENANT_ENV_VAR);
+    let transport = transport_from_matches(crmatch)?;
+    let token = get_bearer_token(&transport, &tenant)?;
+    let (base, caps) = negotiate(crmatch, &transport, base, &token)?;
+    caps.check(&payload, fail_unsupported(crmatch))?;
+    let resp = api::create(&transport, &base, &token, &payload)?;
+    ledger::record(LedgerEntry::created(&payload, &resp));
+    print_response(crmatch, &resp);
+    if let Some(mode) = crmatch.value_of("update-kubeconfig") {
+        let path = kubeconfig_path(crmatch.value_of("kubeconfig"))?;
+        let mut config = Kubeconfig::load(&path)?;
+        let context = crmatch.value_of("context");
+        if mode == "set-namespace" {
+            config.set_namespace(context, &resp.namespace)?;
+        } else {
+            config.add_namespace_context(context, &resp.namespace, &resp.namespace)?;
+            if mode == "use-context" {
+                config.current_context = Some(resp.namespace.clone());
+            }
+        }
+        config.save(&path)?;
+        eprintln!("Updated kubeconfig {}", path.display());
+    }
+    if crmatch.is_present("wait") {
+        let readiness = match crmatch.value_of("wait-via") {
+            Some("kubernetes") => Readiness::Kubernetes(KubeClient::from_kubeconfig(
+                crmatch.value_of("kubeconfig"),
+                crmatch.value_of("context"),
+            )?),
+            _ => Readiness::PlatformApi {
+                transport: &transport,
+                base: &base,
+                token: &token,
+            },
+        };
+        let timeout: u64 = crmatch.value_of("wait-timeout").unwrap().parse().unwrap();
+        readiness.wait(&resp.namespace, Duration::from_secs(timeout))?;
+    }
+    Ok(())
+}
+
+fn run_exec(exmatch: &ArgMatches<'_>) -> Result<(), Error> {
+    let cluster: String = option_or_env!(exmatch, "cluster", CLUSTER_ENV_VAR);
+    let payload = payload_from_matches(exmatch, cluster, &Interpolation::from_matches(exmatch))?;
+    let base = base_url_from_matches(exmatch)?;
+    let tenant: String = option_or_env!(exmatch, "tenant", TENANT_ENV_VAR);
+    let command: Vec<&str> = exmatch.values_of("command").unwrap().collect();
+    let transport = transport_from_matches(exmatch)?;
+    let token = get_bearer_token(&transport, &tenant)?;
+    let (base, caps) = negotiate(exmatch, &transport, base, &token)?;
+    caps.check(&payload, fail_unsupported(exmatch))?;
+    let resp = api::create(&transport, &base, &token, &payload)?;
+    ledger::record(LedgerEntry::created(&payload, &resp));
+    eprintln!("{}", resp);
+    let keep_on_failure = exmatch.is_present("keep-on-failure");
+    let code = run_with_cleanup(
+        &command,
+        &[
+            ("NAMESPACE", &resp.namespace),
+            ("NAMESPACE_EXPIRY", &resp.expiry),
+        ],
+        |code| {
+            if let Some(code) = code.filter(|c| *c != 0 && keep_on_failure) {
+                eprintln!(
+                    "Command exited with {}, keeping namespace {}",
+                    code, resp.namespace
+                );
+                return Ok(());
             }
+            // the command may have outlived the original token
+            get_bearer_token(&transport, &tenant)
+                .and_then(|token| api::delete(&transport, &base, &token, &resp.namespace))
+                .map_err(|e| {
+                    Error::Unknown(format!(
+                        "Error deleting namespace {}: {}",
+                        resp.namespace, e
+                    ))
+                })?;
+            ledger::record(LedgerEntry::deleted(&resp.namespace));
+            eprintln!("Deleted namespace {}", resp.namespace);
+            Ok(())
+        },
+    )?;
+    std::process::exit(code);
+}
+
+fn run_pr(prmatch: &ArgMatches<'_>) -> Result<(), Error> {
+    let event = PullRequestEvent::load(prmatch.value_of("event-path"))?;
+    let productkey = prmatch.value_of("productkey").unwrap();
+    let suffix_from = match prmatch.value_of("suffix-from") {
+        Some("branch") => SuffixFrom::Branch,
+        _ => SuffixFrom::Number,
+    };
+    let name = event.suffix(productkey, suffix_from)?;
+    let full_name = format!("{}-{}", productkey, name);
+    let dry_run = prmatch.occurrences_of("debug") > 0;
+    if event.is_closed() {
+        if dry_run {
+            println!("Pull request closed, would delete namespace {}", full_name);
+            eprintln!("Dry-run, not calling API!");
+            return Ok(());
+        }
+        let base = base_url_from_matches(prmatch)?;
+        let tenant: String = option_or_env!(prmatch, "tenant", TENANT_ENV_VAR);
+        let transport = transport_from_matches(prmatch)?;
+        let token = get_bearer_token(&transport, &tenant)?;
+        let (base, _) = negotiate(prmatch, &transport, base, &token)?;
+        api::delete(&transport, &base, &token, &full_name)?;
+        ledger::record(LedgerEntry::deleted(&full_name));
+        println!("Pull request closed, deleted namespace {}", full_name);
+        return Ok(());
+    }
+    let interp = Interpolation::from_matches(prmatch);
+    let mut metadata = metadata_from_matches(prmatch, &interp)?;
+    event.annotate(&mut metadata);
+    if dry_run {
+        let (base_name, base) = base_url_setting(prmatch);
+        let cluster = Setting::resolve(prmatch, "cluster", CLUSTER_ENV_VAR);
+        let tenant = Setting::resolve(prmatch, "tenant", TENANT_ENV_VAR);
+        let payload = build_payload(
+            prmatch,
+            productkey,
+            name,
+            metadata,
+            cluster.value_or_placeholder(),
+            &interp,
+        )?;
+        DryRun {
+            settings: vec![
+                (base_name, &base),
+                ("cluster", &cluster),
+                ("tenant", &tenant),
+            ],
+            method: "POST",
+            url: dry_run_url(&base),
+            rendered: interp.rendered(),
         }
-        let hostname: String = option_or_env!(crmatch, "hostname", HOSTNAME_ENV_VAR);
-        let cluster: String = option_or_env!(crmatch, "cluster", CLUSTER_ENV_VAR);
-        let tenant: String = option_or_env!(crmatch, "tenant", TENANT_ENV_VAR);
-        let vsas = match_vault_service_accounts(crmatch);
-        let extra = match_extra(crmatch)?;
-        let labelscollected: Labels = metadata.labels.into_iter().map(|a| a.into()).collect();
-        let annotationscollected: Annotations =
-            metadata.annotations.into_iter().map(|a| a.into()).collect();
-        let payload = NSDefBuilder::default()
-            .productkey(productkey)
-            .ttl(ttl)
-            .cluster(cluster)
-            .namespace(name)
-            .labels(labelscollected)
-            .annotations(annotationscollected)
-            .vault_service_accounts(vsas)
-            .extra_properties(extra)
-            .build()
-            .unwrap();
-        if crmatch.occurrences_of("debug") > 0 {
+        .report(&payload);
+        return Ok(());
+    }
+    let cluster: String = option_or_env!(prmatch, "cluster", CLUSTER_ENV_VAR);
+    let payload = build_payload(prmatch, productkey, name, metadata, cluster, &interp)?;
+    let base = base_url_from_matches(prmatch)?;
+    let tenant: String = option_or_env!(prmatch, "tenant", TENANT_ENV_VAR);
+    let transport = transport_from_matches(prmatch)?;
+    let token = get_bearer_token(&transport, &tenant)?;
+    let (base, caps) = negotiate(prmatch, &transport, base, &token)?;
+    caps.check(&payload, fail_unsupported(prmatch))?;
+    let resp = api::create(&transport, &base, &token, &payload)?;
+    ledger::record(LedgerEntry::created(&payload, &resp));
+    print_response(prmatch, &resp);
+    Ok(())
+}
+
+fn run_plan(plmatch: &ArgMatches<'_>) -> Result<(), Error> {
+    let cluster: String = option_or_env!(plmatch, "cluster", CLUSTER_ENV_VAR);
+    let payload = payload_from_matches(plmatch, cluster, &Interpolation::from_matches(plmatch))?;
+    let base = base_url_from_matches(plmatch)?;
+    let tenant: String = option_or_env!(plmatch, "tenant", TENANT_ENV_VAR);
+    let full_name = format!("{}-{}", payload.productkey, payload.namespace);
+    let transport = transport_from_matches(plmatch)?;
+    let token = get_bearer_token(&transport, &tenant)?;
+    let (base, caps) = negotiate(plmatch, &transport, base, &token)?;
+    caps.check(&payload, fail_unsupported(plmatch))?;
+    let live = match api::get(&transport, &base, &token, &full_name)? {
+        Some(live) => live,
+        None => {
             println!(
-                "Would submit the following payload to the API:\n{}",
+                "Namespace {} does not exist and would be created with:\n{}",
+                full_name,
                 serde_json::to_string_pretty(&payload).unwrap()
             );
-            eprintln!("Dry-run, not calling API!");
             return Ok(());
         }
-        //std::process::exit(match create(&hostname, &tenant, payload) {
-        //    Ok(r) => {
-        //        println!("{}", r);
-        //        0
-        //    }
-        //    Err(e) => {
-        //        eprintln!("{}", e);
-        //        1
-        //    }
-        //});
-        let resp = create(&hostname, &tenant, payload)?;
-        println!("{}", resp);
+    };
+    let changes = plan::diff(&payload, &live);
+    if changes.is_empty() {
+        println!("No changes to namespace {}.", full_name);
+    } else {
+        println!("Changes to namespace {}:", full_name);
+        for change in changes {
+            println!("  {}", change);
+        }
+    }
+    if let Some(expiry) = live.get("expiry").and_then(|e| e.as_str()) {
+        println!(
+            "Current expiry {} would be reset using ttl {}.",
+            expiry, payload.ttl
+        );
+    }
+    Ok(())
+}
+
+fn run_keepalive(kamatch: &ArgMatches<'_>) -> Result<(), Error> {
+    let payloads = payloads_from_spec(kamatch, &Interpolation::from_matches(kamatch))?;
+    let renew_before = parse_duration(kamatch.value_of("renew-before").unwrap()).unwrap();
+    let deadline = DateTime::parse_from_rfc3339(kamatch.value_of("until").unwrap())
+        .unwrap()
+        .with_timezone(&Utc);
+    let base = base_url_from_matches(kamatch)?;
+    let tenant: String = option_or_env!(kamatch, "tenant", TENANT_ENV_VAR);
+    let transport = transport_from_matches(kamatch)?;
+    let token = get_bearer_token(&transport, &tenant)?;
+    let (base, caps) = negotiate(kamatch, &transport, base, &token)?;
+    for payload in &payloads {
+        caps.check(payload, fail_unsupported(kamatch))?;
+    }
+    keepalive::run(
+        payloads,
+        chrono::Duration::from_std(renew_before).unwrap(),
+        deadline,
+        |payload| {
+            // renewals are hours apart, so always fetch a fresh token
+            let token = get_bearer_token(&transport, &tenant)?;
+            let resp = api::create(&transport, &base, &token, payload)?;
+            ledger::record(LedgerEntry::created(payload, &resp));
+            Ok(resp)
+        },
+    )
+}
+
+fn confirm(prompt: &str) -> Result<bool, Error> {
+    eprint!("{} [y/N] ", prompt);
+    io::stderr().flush().ok();
+    let mut answer = String::new();
+    io::stdin()
+        .read_line(&mut answer)
+        .map_err(|e| Error::Unknown(format!("Could not read confirmation: {}", e)))?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn run_gc(gcmatch: &ArgMatches<'_>) -> Result<(), Error> {
+    let productkey = gcmatch.value_of("productkey").unwrap();
+    let mut filter = Filter {
+        older_than: gcmatch
+            .value_of("older-than")
+            .and_then(parse_duration)
+            .map(|d| chrono::Duration::from_std(d).unwrap()),
+        name_regex: gcmatch
+            .value_of("name-regex")
+            .map(|re| Regex::new(re).unwrap()),
+        ..Filter::default()
+    };
+    if let Some(selectors) = gcmatch.values_of("selector") {
+        for sel in selectors {
+            filter.selectors.extend(Selector::parse_all(sel)?);
+        }
+    }
+    let base = base_url_from_matches(gcmatch)?;
+    let tenant: String = option_or_env!(gcmatch, "tenant", TENANT_ENV_VAR);
+    let transport = transport_from_matches(gcmatch)?;
+    let token = get_bearer_token(&transport, &tenant)?;
+    let (base, _) = negotiate(gcmatch, &transport, base, &token)?;
+    let now = Utc::now();
+    let matched: Vec<String> = api::list(&transport, &base, &token, productkey)?
+        .iter()
+        .filter(|ns| filter.matches(ns, now))
+        .filter_map(gc::name_of)
+        .map(String::from)
+        .collect();
+    if matched.is_empty() {
+        println!("No namespaces matched.");
+        return Ok(());
+    }
+    println!("The following namespaces matched:");
+    for name in &matched {
+        println!("  {}", name);
+    }
+    if gcmatch.occurrences_of("debug") > 0 {
+        eprintln!("Dry-run, not deleting!");
+        return Ok(());
+    }
+    if !gcmatch.is_present("yes") && !confirm(&format!("Delete {} namespaces?", matched.len()))? {
+        eprintln!("Aborted.");
+        return Ok(());
+    }
+    let concurrency: usize = gcmatch.value_of("concurrency").unwrap().parse().unwrap();
+    let total = matched.len();
+    let token = Arc::new(token);
+    let results = run_concurrently(matched, concurrency, move |name| {
+        let result = api::delete(&transport, &base, &token, &name);
+        (name, result)
+    });
+    let mut failed = 0;
+    for (name, result) in results {
+        match result {
+            Ok(()) => {
+                println!("deleted {}", name);
+                ledger::record(LedgerEntry::deleted(&name));
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {}", name, e);
+            }
+        }
+    }
+    println!("{} deleted, {} failed", total - failed, failed);
+    if failed > 0 {
+        Err(Error::Unknown(format!(
+            "{} of {} namespaces failed to delete",
+            failed, total
+        )))
+    } else {
         Ok(())
+    }
+}
+
+fn run_history(himatch: &ArgMatches<'_>) -> Result<(), Error> {
+    let mut entries = ledger::read_entries()?;
+    if himatch.is_present("active") {
+        entries = ledger::active(entries, Utc::now());
+    }
+    for entry in entries {
+        let action = match entry.action {
+            Action::Created => "created",
+            Action::Deleted => "deleted",
+        };
+        println!(
+            "{}  {:<7}  {}  cluster: {}  expiry: {}  by: {}{}",
+            entry.timestamp.to_rfc3339_opts(SecondsFormat::Secs, true),
+            action,
+            entry.namespace,
+            entry.cluster.as_deref().unwrap_or("-"),
+            entry.expiry.as_deref().unwrap_or("-"),
+            entry.user.as_deref().unwrap_or("-"),
+            entry
+                .ci_job
+                .map(|job| format!(" ({})", job))
+                .unwrap_or_default(),
+        );
+    }
+    Ok(())
+}
+
+fn payloads_from_spec(
+    matches: &ArgMatches<'_>,
+    interp: &Interpolation,
+) -> Result<Vec<NSDef>, Error> {
+    let specs = read_specs(matches.value_of("spec").unwrap(), interp)?;
+    if specs.is_empty() {
+        return Err(Error::Unknown("no namespaces found in spec".to_string()));
+    }
+    let default_cluster = matches
+        .value_of("cluster")
+        .map(String::from)
+        .or_else(|| env::var(CLUSTER_ENV_VAR).ok());
+    specs
+        .into_iter()
+        .map(|s| {
+            s.into_nsdef(
+                default_cluster.as_deref(),
+                matches.is_present("allow-override"),
+            )
+        })
+        .collect()
+}
+
+fn run_apply(apmatch: &ArgMatches<'_>) -> Result<(), Error> {
+    let interp = Interpolation::from_matches(apmatch);
+    let payloads = payloads_from_spec(apmatch, &interp)?;
+    if apmatch.occurrences_of("debug") > 0 {
+        let (base_name, base) = base_url_setting(apmatch);
+        let tenant = Setting::resolve(apmatch, "tenant", TENANT_ENV_VAR);
+        let dry_run = DryRun {
+            settings: vec![(base_name, &base), ("tenant", &tenant)],
+            method: "POST",
+            url: dry_run_url(&base),
+            rendered: interp.rendered(),
+        };
+        dry_run.report_settings();
+        for payload in &payloads {
+            dry_run.report_request(payload);
+        }
+        eprintln!("Dry-run, not calling API!");
+        return Ok(());
+    }
+    let base = base_url_from_matches(apmatch)?;
+    let tenant: String = option_or_env!(apmatch, "tenant", TENANT_ENV_VAR);
+    let concurrency: usize = apmatch.value_of("concurrency").unwrap().parse().unwrap();
+    let transport = transport_from_matches(apmatch)?;
+    let token = get_bearer_token(&transport, &tenant)?;
+    let (base, caps) = negotiate(apmatch, &transport, base, &token)?;
+    for payload in &payloads {
+        caps.check(payload, fail_unsupported(apmatch))?;
+    }
+    let token = Arc::new(token);
+    let total = payloads.len();
+    let results = run_concurrently(payloads, concurrency, move |payload| {
+        let result = api::create(&transport, &base, &token, &payload);
+        (payload, result)
+    });
+    let mut failed = 0;
+    for (payload, result) in results {
+        match result {
+            Ok(resp) => {
+                println!("ok      {} (expiry: {})", resp.namespace, resp.expiry);
+                ledger::record(LedgerEntry::created(&payload, &resp));
+            }
+            Err(e) => {
+                failed += 1;
+                println!(
+                    "FAILED  {}-{}: {}",
+                    payload.productkey, payload.namespace, e
+                );
+            }
+        }
+    }
+    println!("{} succeeded, {} failed", total - failed, failed);
+    if failed > 0 {
+        Err(Error::Unknown(format!(
+            "{} of {} namespaces failed",
+            failed, total
+        )))
     } else {
-        panic!("No subcommand");
+        Ok(())
+    }
+}
+
+fn run_mock_server(mkmatch: &ArgMatches<'_>) -> Result<(), Error> {
+    // both were checked by their validators
+    let mut faults: Vec<Fault> = mkmatch
+        .values_of("fail")
+        .into_iter()
+        .flatten()
+        .filter_map(|f| Fault::parse_failure(f).ok())
+        .collect();
+    faults.extend(
+        mkmatch
+            .values_of("latency")
+            .into_iter()
+            .flatten()
+            .filter_map(|l| Fault::parse_latency(l).ok()),
+    );
+    mock::serve(
+        mkmatch.value_of("listen").unwrap(),
+        faults,
+        mkmatch.value_of("dump"),
+    )
+}
+
+fn run_schema(scmatch: &ArgMatches<'_>) {
+    let kind = match scmatch.value_of("kind") {
+        Some("extra-data") => Kind::ExtraData,
+        Some("payload") => Kind::Payload,
+        _ => Kind::Spec,
+    };
+    println!("{}", serde_json::to_string_pretty(&schema(kind)).unwrap());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_matches(args: &[&str]) -> ArgMatches<'static> {
+        let argv = ["platformapi-namespace", "create"]
+            .iter()
+            .chain(args)
+            .chain(&["demo", "app"]);
+        let matches = app().get_matches_from(argv);
+        matches.subcommand_matches("create").unwrap().clone()
+    }
+
+    // the base URL env vars are only set here, so this can't race with
+    // other tests
+    #[test]
+    fn base_url_precedence() {
+        let resolve = |args: &[&str]| {
+            let (name, setting) = base_url_setting(&create_matches(args));
+            (name, setting.to_string(), dry_run_url(&setting))
+        };
+        env::set_var(BASE_URL_ENV_VAR, "http://env-base:8080/platform");
+        env::set_var(HOSTNAME_ENV_VAR, "env-host");
+        let from_base_url = resolve(&["--base-url", "http://flag:8080"]);
+        let from_hostname = resolve(&["--hostname", "flag-host"]);
+        let from_base_env = resolve(&[]);
+        env::remove_var(BASE_URL_ENV_VAR);
+        let from_host_env = resolve(&[]);
+        env::remove_var(HOSTNAME_ENV_VAR);
+        let unset = resolve(&[]);
+
+        assert_eq!(
+            from_base_url,
+            (
+                "base-url",
+                "http://flag:8080 (from --base-url option)".to_string(),
+                "http://flag:8080/namespace".to_string()
+            )
+        );
+        assert_eq!(from_hostname.0, "hostname");
+        assert_eq!(from_hostname.2, "https://flag-host/namespace");
+        assert_eq!(from_base_env.0, "base-url");
+        assert_eq!(
+            from_base_env.1,
+            "http://env-base:8080/platform (from PLATFORM_API_BASE_URL env var)"
+        );
+        assert_eq!(from_base_env.2, "http://env-base:8080/platform/namespace");
+        assert_eq!(from_host_env.0, "hostname");
+        assert_eq!(from_host_env.2, "https://env-host/namespace");
+        assert_eq!(unset.0, "hostname");
+        assert_eq!(
+            unset.1,
+            "unset (neither --hostname nor PLATFORM_API_HOSTNAME)"
+        );
+        assert_eq!(unset.2, "https://<hostname>/namespace");
     }
 }
@@ -1,10 +1,12 @@
 use clap::ArgMatches;
 use klap::{annotation_from_str, labels_from_str_either, AnnotationMap, Label, LabelMap};
 use serde::Deserialize;
-use serde_yaml::{from_reader, from_str};
+use serde_yaml::{from_value, Deserializer, Value};
 use std::fs;
-use std::io::BufReader;
+use std::io::{self, Read};
 
+use crate::interpolate::Interpolation;
+use crate::provenance::Provenance;
 use crate::types::Error;
 
 #[derive(Debug, Clone, Deserialize, Default)]
@@ -16,26 +18,149 @@ pub struct Metadata {
     pub annotations: AnnotationMap,
 }
 
-#[derive(Debug, Clone, Deserialize)]
-struct Manifest {
-    metadata: Metadata,
+// a document of a manifest stream, only deserialized once it's been
+// selected so that documents we don't want can't fail the command
+struct Document {
+    index: usize,
+    value: Value,
 }
 
-fn parse_metadata(input: &str) -> Result<Metadata, Error> {
-    let res: Result<Manifest, _> = if let Some(filename) = input.strip_prefix('@') {
-        let f = fs::File::open(filename).map_err(|v| {
-            Error::Option(
-                "metadata-from-manifest".to_string(),
-                input.to_string(),
-                v.to_string(),
-            )
+impl Document {
+    fn field(&self, path: &[&str]) -> Option<&str> {
+        path.iter()
+            .try_fold(&self.value, |v, key| v.get(*key))
+            .and_then(Value::as_str)
+    }
+
+    fn kind(&self) -> Option<&str> {
+        self.field(&["kind"])
+    }
+
+    fn name(&self) -> Option<&str> {
+        self.field(&["metadata", "name"])
+    }
+
+    fn describe(&self) -> String {
+        format!(
+            "{}/{}",
+            self.kind().unwrap_or("<no kind>"),
+            self.name().unwrap_or("<no name>")
+        )
+    }
+
+    fn metadata(self, input: &str) -> Result<Metadata, Error> {
+        let metadata = self.value.get("metadata").cloned().ok_or_else(|| {
+            manifest_error(input, format!("document {} has no metadata", self.index))
         })?;
-        from_reader(BufReader::new(f))
-    } else {
-        from_str(input)
+        from_value(metadata)
+            .map_err(|e| manifest_error(input, format!("document {}: {}", self.index, e)))
+    }
+}
+
+fn manifest_error(input: &str, msg: String) -> Error {
+    Error::Option("metadata-from-manifest".to_string(), input.to_string(), msg)
+}
+
+fn read_manifest_input(
+    input: &str,
+    interp: &Interpolation,
+    stdin: &mut dyn Read,
+) -> Result<String, Error> {
+    let mut contents = String::new();
+    match input.strip_prefix('@') {
+        Some("-") => stdin
+            .read_to_string(&mut contents)
+            .map_err(|e| manifest_error(input, e.to_string()))?,
+        Some(filename) => fs::File::open(filename)
+            .and_then(|mut f| f.read_to_string(&mut contents))
+            .map_err(|e| manifest_error(input, e.to_string()))?,
+        None => return Ok(input.to_string()),
     };
-    res.map(|v| v.metadata)
-        .map_err(|e| Error::Unknown(e.to_string()))
+    interp.render(input, contents)
+}
+
+// pick the document to take metadata from. with a 'kind/name' selector that
+// document must exist, otherwise a lone document is used as is, or the only
+// Namespace among several.
+fn select_manifest(
+    input: &str,
+    documents: Vec<Document>,
+    selector: Option<&str>,
+) -> Result<Document, Error> {
+    let mut candidates: Vec<Document> = match selector {
+        Some(sel) => {
+            let (kind, name) = sel.split_once('/').ok_or_else(|| {
+                Error::Option(
+                    "manifest-selector".to_string(),
+                    sel.to_string(),
+                    "expected 'kind/name'".to_string(),
+                )
+            })?;
+            documents
+                .into_iter()
+                .filter(|d| {
+                    matches!(d.kind(), Some(k) if k.eq_ignore_ascii_case(kind))
+                        && d.name() == Some(name)
+                })
+                .collect()
+        }
+        None if documents.len() == 1 => documents,
+        None => documents
+            .into_iter()
+            .filter(|d| d.kind() == Some("Namespace"))
+            .collect(),
+    };
+    match candidates.len() {
+        1 => Ok(candidates.remove(0)),
+        0 => Err(manifest_error(
+            input,
+            match selector {
+                Some(sel) => format!("no document matching '{}' found", sel),
+                None => {
+                    "no Namespace document found, use --manifest-selector to choose one".to_string()
+                }
+            },
+        )),
+        _ => Err(manifest_error(
+            input,
+            format!(
+                "multiple documents match ({}), use --manifest-selector to choose one",
+                candidates
+                    .iter()
+                    .map(Document::describe)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        )),
+    }
+}
+
+// documents are numbered from 1, counting empty ones, as they appear in the
+// stream
+fn parse_documents(input: &str, contents: &str) -> Result<Vec<Document>, Error> {
+    let mut documents = Vec::new();
+    for (i, doc) in Deserializer::from_str(contents).enumerate() {
+        let value = Value::deserialize(doc)
+            .map_err(|e| manifest_error(input, format!("document {}: {}", i + 1, e)))?;
+        if !value.is_null() {
+            documents.push(Document {
+                index: i + 1,
+                value,
+            });
+        }
+    }
+    Ok(documents)
+}
+
+fn parse_metadata(
+    input: &str,
+    selector: Option<&str>,
+    interp: &Interpolation,
+    stdin: &mut dyn Read,
+) -> Result<Metadata, Error> {
+    let contents = read_manifest_input(input, interp, stdin)?;
+    let documents = parse_documents(input, &contents)?;
+    select_manifest(input, documents, selector)?.metadata(input)
 }
 
 fn match_labels(matches: &ArgMatches<'_>, labels: &mut LabelMap) -> Result<(), Error> {
@@ -81,14 +206,160 @@ fn match_annotations(
     Ok(())
 }
 
-pub fn metadata_from_matches(matches: &ArgMatches<'_>) -> Result<Metadata, Error> {
+pub fn metadata_from_matches(
+    matches: &ArgMatches<'_>,
+    interp: &Interpolation,
+) -> Result<Metadata, Error> {
     let mut metadata: Metadata;
     if let Some(manifest) = matches.value_of("manifest") {
-        metadata = parse_metadata(manifest)?;
+        metadata = parse_metadata(
+            manifest,
+            matches.value_of("manifest-selector"),
+            interp,
+            &mut io::stdin(),
+        )?;
     } else {
         metadata = Metadata::default();
     }
     match_labels(matches, &mut metadata.labels)?;
     match_annotations(matches, &mut metadata.annotations)?;
+    if matches.is_present("provenance") {
+        Provenance::detect().merge_into(&mut metadata);
+    }
     Ok(metadata)
 }
+
+// replace runs of characters not accepted by `keep` with a single '-' and trim
+// the result to `max` characters, so that it starts and ends alphanumerically
+fn sanitize(input: &str, max: usize, keep: fn(char) -> bool) -> String {
+    let mut out = String::with_capacity(input.len());
+    for c in input.chars() {
+        if keep(c) {
+            out.push(c);
+        } else if !out.ends_with('-') {
+            out.push('-');
+        }
+    }
+    let out: String = out
+        .trim_matches(|c: char| !c.is_ascii_alphanumeric())
+        .chars()
+        .take(max)
+        .collect();
+    out.trim_end_matches(|c: char| !c.is_ascii_alphanumeric())
+        .to_string()
+}
+
+// make a string usable as a kubernetes label value
+pub fn sanitize_label_value(input: &str) -> String {
+    sanitize(input, 63, |c| {
+        c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.'
+    })
+}
+
+// make a string usable as (part of) a namespace name, at most `max` characters
+pub fn sanitize_dns_label(input: &str, max: usize) -> String {
+    sanitize(&input.to_ascii_lowercase(), max, |c| {
+        c.is_ascii_lowercase() || c.is_ascii_digit()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const STREAM: &str = "\
+apiVersion: v1
+kind: ConfigMap
+metadata:
+  name: settings
+  labels: [not, a, map]
+---
+apiVersion: v1
+kind: Namespace
+metadata:
+  name: app
+  labels:
+    team: core
+  annotations:
+    owner: me
+---
+apiVersion: apps/v1
+kind: Deployment
+metadata:
+  name: app
+  labels:
+    tier: web
+";
+
+    fn parse(input: &str, selector: Option<&str>, stdin: &str) -> Result<Metadata, Error> {
+        let interp = Interpolation::new(false, false);
+        parse_metadata(input, selector, &interp, &mut Cursor::new(stdin))
+    }
+
+    fn message(result: Result<Metadata, Error>) -> String {
+        match result {
+            Err(Error::Option(_, _, msg)) => msg,
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn namespace_from_stdin() {
+        let metadata = parse("@-", None, STREAM).unwrap();
+        assert_eq!(metadata.name.as_deref(), Some("app"));
+        assert_eq!(metadata.labels["team"], "core");
+        assert_eq!(metadata.annotations["owner"], "me");
+    }
+
+    #[test]
+    fn select_by_kind_and_name() {
+        let metadata = parse(STREAM, Some("deployment/app"), "").unwrap();
+        assert_eq!(metadata.labels["tier"], "web");
+        assert!(message(parse(STREAM, Some("Deployment/other"), "")).contains("no document"));
+        assert!(parse(STREAM, Some("Deployment"), "").is_err());
+    }
+
+    // the ConfigMap's labels are malformed, which only matters once it's chosen
+    #[test]
+    fn broken_documents_only_fail_when_selected() {
+        assert!(parse(STREAM, Some("Namespace/app"), "").is_ok());
+        let msg = message(parse(STREAM, Some("ConfigMap/settings"), ""));
+        assert!(msg.starts_with("document 1:"), "{}", msg);
+    }
+
+    #[test]
+    fn ambiguous_or_missing_namespace() {
+        let two = format!("{}---\nkind: Namespace\nmetadata:\n  name: other\n", STREAM);
+        let msg = message(parse(&two, None, ""));
+        assert!(msg.contains("Namespace/app, Namespace/other"), "{}", msg);
+        let none = "kind: A\nmetadata: {name: a}\n---\nkind: B\nmetadata: {name: b}\n";
+        assert!(message(parse(none, None, "")).contains("no Namespace document"));
+        let lone = "kind: Deployment\nmetadata: {name: web}\n";
+        assert_eq!(parse(lone, None, "").unwrap().name.as_deref(), Some("web"));
+        assert!(
+            message(parse("kind: Namespace\n", None, "")).contains("document 1 has no metadata")
+        );
+    }
+
+    #[test]
+    fn sanitize_values() {
+        assert_eq!(
+            sanitize_label_value("feature/My Branch!"),
+            "feature-My-Branch"
+        );
+        assert_eq!(sanitize_label_value("--a__b.."), "a__b");
+        assert_eq!(sanitize_label_value(&"x".repeat(70)).len(), 63);
+        assert_eq!(
+            sanitize_label_value(&format!("{}-y", "x".repeat(62))),
+            "x".repeat(62)
+        );
+        assert_eq!(
+            sanitize_dns_label("Feature/My_Branch", 63),
+            "feature-my-branch"
+        );
+        assert_eq!(sanitize_dns_label("PR 1234: fix", 6), "pr-123");
+        assert_eq!(sanitize_dns_label("ab-cd", 3), "ab");
+        assert_eq!(sanitize_dns_label("!!!", 10), "");
+    }
+}
@@ -0,0 +1,678 @@
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use crate::cli::parse_duration;
+use crate::types::Error;
+
+// form fields never written to the request log or dump file
+const REDACTED_FIELDS: &[&str] = &["client_secret"];
+const MAX_BODY_BYTES: usize = 10 * 1024 * 1024;
+const READ_TIMEOUT_SECS: u64 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Route {
+    Token,
+    Create,
+    Get,
+    List,
+    Delete,
+}
+
+impl Route {
+    fn parse(inp: &str) -> Option<Route> {
+        match inp {
+            "token" => Some(Route::Token),
+            "create" => Some(Route::Create),
+            "get" => Some(Route::Get),
+            "list" => Some(Route::List),
+            "delete" => Some(Route::Delete),
+            _ => None,
+        }
+    }
+}
+
+// a scripted misbehaviour for one route. `status` replaces the real response
+// and `latency_ms` delays it, for the next `times` requests or forever.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fault {
+    pub route: Route,
+    #[serde(default)]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+impl Fault {
+    // 'create=503' or 'create=503x2'
+    pub fn parse_failure(inp: &str) -> Result<Fault, String> {
+        let (route, spec) = split_route(inp)?;
+        let (status, times) = match spec.split_once('x') {
+            Some((status, times)) => (status, Some(times)),
+            None => (spec, None),
+        };
+        let status = match status.parse::<u16>() {
+            Ok(s) if (100..600).contains(&s) => s,
+            _ => return Err(format!("invalid status code '{}'", status)),
+        };
+        let times = match times {
+            Some(t) => Some(
+                t.parse::<u32>()
+                    .map_err(|_| format!("invalid repeat count '{}'", t))?,
+            ),
+            None => None,
+        };
+        Ok(Fault {
+            route,
+            status: Some(status),
+            latency_ms: None,
+            times,
+        })
+    }
+
+    // 'get=1500', milliseconds
+    pub fn parse_latency(inp: &str) -> Result<Fault, String> {
+        let (route, spec) = split_route(inp)?;
+        let latency = spec
+            .parse::<u64>()
+            .map_err(|_| format!("invalid latency '{}'", spec))?;
+        Ok(Fault {
+            route,
+            status: None,
+            latency_ms: Some(latency),
+            times: None,
+        })
+    }
+}
+
+fn split_route(inp: &str) -> Result<(Route, &str), String> {
+    let (route, spec) = inp
+        .split_once('=')
+        .ok_or_else(|| String::from("expected <route>=<value>"))?;
+    let route = Route::parse(route).ok_or_else(|| {
+        format!(
+            "unknown route '{}', expected one of token, create, get, list, delete",
+            route
+        )
+    })?;
+    Ok((route, spec))
+}
+
+#[derive(Debug, Clone, Serialize)]
+struct Received {
+    time: String,
+    method: String,
+    path: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    query: BTreeMap<String, String>,
+    body: Value,
+}
+
+#[derive(Default)]
+struct State {
+    namespaces: BTreeMap<String, Value>,
+    faults: Vec<Fault>,
+    received: Vec<Received>,
+    dump: Option<File>,
+    tokens_issued: u64,
+}
+
+impl State {
+    fn purge_expired(&mut self, now: DateTime<Utc>) {
+        self.namespaces.retain(|_, ns| {
+            let expiry = ns
+                .get("expiry")
+                .and_then(Value::as_str)
+                .and_then(|e| DateTime::parse_from_rfc3339(e).ok());
+            !matches!(expiry, Some(e) if e <= now)
+        });
+    }
+
+    // apply every fault scripted for the route, dropping those used up. the
+    // first status and the longest latency win.
+    fn take_faults(&mut self, route: Route) -> (Option<u16>, Option<u64>) {
+        let (mut status, mut latency) = (None, None);
+        for fault in self.faults.iter_mut().filter(|f| f.route == route) {
+            status = status.or(fault.status);
+            latency = latency.max(fault.latency_ms);
+            fault.times = fault.times.map(|n| n.saturating_sub(1));
+        }
+        self.faults.retain(|f| f.times != Some(0));
+        (status, latency)
+    }
+
+    fn record(&mut self, received: Received) {
+        if let Some(dump) = self.dump.as_mut() {
+            if let Ok(line) = serde_json::to_string(&received) {
+                if let Err(e) = writeln!(dump, "{}", line) {
+                    eprintln!("could not write to dump file: {}", e);
+                }
+            }
+        }
+        self.received.push(received);
+    }
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    query: BTreeMap<String, String>,
+    headers: BTreeMap<String, String>,
+    body: String,
+}
+
+struct HttpResponse {
+    status: u16,
+    body: String,
+}
+
+impl HttpResponse {
+    fn json(status: u16, body: Value) -> Self {
+        HttpResponse {
+            status,
+            body: body.to_string(),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        HttpResponse::json(status, json!({ "message": message }))
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        504 => "Gateway Timeout",
+        _ => "Unknown",
+    }
+}
+
+fn timestamp(t: DateTime<Utc>) -> String {
+    t.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn parse_query(query: &str) -> BTreeMap<String, String> {
+    serde_urlencoded::from_str(query).unwrap_or_default()
+}
+
+fn read_request(stream: &TcpStream) -> Result<HttpRequest, String> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .map_err(|e| format!("could not read request: {}", e))?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m.to_string(), t.to_string()),
+        _ => return Err(format!("malformed request line '{}'", line.trim())),
+    };
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut header = String::new();
+        reader
+            .read_line(&mut header)
+            .map_err(|e| format!("could not read headers: {}", e))?;
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            headers.insert(k.trim().to_ascii_lowercase(), v.trim().to_string());
+        }
+    }
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|l| l.parse().ok())
+        .unwrap_or(0);
+    if length > MAX_BODY_BYTES {
+        return Err(format!("request body of {} bytes is too large", length));
+    }
+    let mut body = vec![0; length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|e| format!("could not read body: {}", e))?;
+    let (path, query) = match target.split_once('?') {
+        Some((p, q)) => (p.to_string(), parse_query(q)),
+        None => (target, BTreeMap::new()),
+    };
+    Ok(HttpRequest {
+        method,
+        path,
+        query,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+fn write_response(mut stream: &TcpStream, resp: &HttpResponse) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        resp.status,
+        reason(resp.status),
+        resp.body.len(),
+        resp.body
+    )?;
+    stream.flush()
+}
+
+// request bodies are kept for assertions, as JSON where possible
+fn received_body(req: &HttpRequest) -> Value {
+    if req.body.is_empty() {
+        return Value::Null;
+    }
+    if let Ok(v) = serde_json::from_str(&req.body) {
+        return v;
+    }
+    let mut form: Map<String, Value> = parse_query(&req.body)
+        .into_iter()
+        .map(|(k, v)| (k, Value::String(v)))
+        .collect();
+    if form.is_empty() {
+        return Value::String(req.body.clone());
+    }
+    for field in REDACTED_FIELDS {
+        if let Some(v) = form.get_mut(*field) {
+            *v = Value::String("REDACTED".to_string());
+        }
+    }
+    Value::Object(form)
+}
+
+fn route_of(req: &HttpRequest) -> Option<Route> {
+    let segments: Vec<&str> = req.path.trim_matches('/').split('/').collect();
+    match (req.method.as_str(), segments.as_slice()) {
+        ("POST", [_, "oauth2", "v2.0", "token"]) => Some(Route::Token),
+        ("POST", ["namespace"]) => Some(Route::Create),
+        ("GET", ["namespace"]) => Some(Route::List),
+        ("GET", ["namespace", _]) => Some(Route::Get),
+        ("DELETE", ["namespace", _]) => Some(Route::Delete),
+        _ => None,
+    }
+}
+
+fn authorized(state: &State, req: &HttpRequest) -> bool {
+    let issued = req
+        .headers
+        .get("authorization")
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .and_then(|t| t.strip_prefix("mock-token-"))
+        .and_then(|n| n.parse::<u64>().ok());
+    matches!(issued, Some(n) if n > 0 && n <= state.tokens_issued)
+}
+
+fn issue_token(state: &mut State, req: &HttpRequest) -> HttpResponse {
+    let form = parse_query(&req.body);
+    if form.get("grant_type").map(String::as_str) != Some("client_credentials") {
+        return HttpResponse::json(400, json!({ "error": "unsupported_grant_type" }));
+    }
+    if !form.contains_key("client_id") || !form.contains_key("client_secret") {
+        return HttpResponse::json(401, json!({ "error": "invalid_client" }));
+    }
+    state.tokens_issued += 1;
+    HttpResponse::json(
+        200,
+        json!({
+            "token_type": "Bearer",
+            "expires_in": 3599,
+            "access_token": format!("mock-token-{}", state.tokens_issued),
+        }),
+    )
+}
+
+fn create_namespace(state: &mut State, req: &HttpRequest, now: DateTime<Utc>) -> HttpResponse {
+    let mut payload = match serde_json::from_str::<Value>(&req.body) {
+        Ok(Value::Object(map)) => map,
+        _ => return HttpResponse::error(400, "request body must be a JSON object"),
+    };
+    let field = |name: &str| payload.get(name).and_then(Value::as_str).map(String::from);
+    let (productkey, namespace, ttl) = match (field("productkey"), field("namespace"), field("ttl"))
+    {
+        (Some(p), Some(n), Some(t)) => (p, n, t),
+        _ => return HttpResponse::error(400, "productkey, namespace and ttl are required"),
+    };
+    if field("cluster").is_none() {
+        return HttpResponse::error(400, "cluster is required");
+    }
+    let ttl = match parse_duration(&ttl) {
+        Some(ttl) => ttl,
+        None => return HttpResponse::error(400, &format!("invalid ttl '{}'", ttl)),
+    };
+    let name = format!("{}-{}", productkey, namespace);
+    let expiry = timestamp(now + chrono::Duration::seconds(ttl.as_secs() as i64));
+    let created = state
+        .namespaces
+        .get(&name)
+        .and_then(|ns| ns.get("created").cloned())
+        .unwrap_or_else(|| Value::String(timestamp(now)));
+    payload.insert("namespace".to_string(), Value::String(name.clone()));
+    payload.insert("expiry".to_string(), Value::String(expiry.clone()));
+    payload.insert("created".to_string(), created);
+    payload.insert("status".to_string(), json!({ "phase": "Active" }));
+    state
+        .namespaces
+        .insert(name.clone(), Value::Object(payload));
+    HttpResponse::json(
+        200,
+        json!({
+            "message": format!("Namespace {} created or updated.", name),
+            "namespace": name,
+            "expiry": expiry,
+            "status": {"phase": "Active"},
+        }),
+    )
+}
+
+fn namespace_name(req: &HttpRequest) -> &str {
+    req.path
+        .trim_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+}
+
+fn handle_api(state: &mut State, req: &HttpRequest, route: Route) -> HttpResponse {
+    let now = Utc::now();
+    state.purge_expired(now);
+    if route != Route::Token && !authorized(state, req) {
+        return HttpResponse::error(401, "missing or invalid bearer token");
+    }
+    match route {
+        Route::Token => issue_token(state, req),
+        Route::Create => create_namespace(state, req, now),
+        Route::Get => match state.namespaces.get(namespace_name(req)) {
+            Some(ns) => HttpResponse::json(200, ns.clone()),
+            None => HttpResponse::error(404, "namespace not found"),
+        },
+        Route::List => {
+            let productkey = req.query.get("productkey").cloned().unwrap_or_default();
+            let items: Vec<Value> = state
+                .namespaces
+                .values()
+                .filter(|ns| {
+                    productkey.is_empty()
+                        || ns.get("productkey").and_then(Value::as_str) == Some(&productkey)
+                })
+                .cloned()
+                .collect();
+            HttpResponse::json(200, Value::Array(items))
+        }
+        Route::Delete => match state.namespaces.remove(namespace_name(req)) {
+            Some(_) => HttpResponse::json(200, json!({ "message": "deleted" })),
+            None => HttpResponse::error(404, "namespace not found"),
+        },
+    }
+}
+
+// endpoints under /_mock let test suites inspect and script the server
+fn handle_control(state: &mut State, req: &HttpRequest) -> HttpResponse {
+    match (req.method.as_str(), req.path.as_str()) {
+        ("GET", "/_mock/requests") => HttpResponse::json(
+            200,
+            serde_json::to_value(&state.received).unwrap_or_default(),
+        ),
+        ("DELETE", "/_mock/requests") => {
+            state.received.clear();
+            HttpResponse::json(200, json!({ "message": "cleared" }))
+        }
+        ("GET", "/_mock/namespaces") => HttpResponse::json(
+            200,
+            Value::Array(state.namespaces.values().cloned().collect()),
+        ),
+        ("GET", "/_mock/faults") => {
+            HttpResponse::json(200, serde_json::to_value(&state.faults).unwrap_or_default())
+        }
+        ("POST", "/_mock/faults") => match serde_json::from_str::<Vec<Fault>>(&req.body) {
+            Ok(faults) => {
+                state.faults.extend(faults);
+                HttpResponse::json(200, serde_json::to_value(&state.faults).unwrap_or_default())
+            }
+            Err(e) => HttpResponse::error(400, &format!("invalid faults: {}", e)),
+        },
+        ("DELETE", "/_mock/faults") => {
+            state.faults.clear();
+            HttpResponse::json(200, json!({ "message": "cleared" }))
+        }
+        ("POST", "/_mock/reset") => {
+            state.namespaces.clear();
+            state.faults.clear();
+            state.received.clear();
+            HttpResponse::json(200, json!({ "message": "reset" }))
+        }
+        _ => HttpResponse::error(404, "unknown mock control endpoint"),
+    }
+}
+
+// a handler panicking mid-request mustn't take every later request down with it
+fn lock(state: &Mutex<State>) -> MutexGuard<'_, State> {
+    state.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn handle(state: &Mutex<State>, req: HttpRequest) -> HttpResponse {
+    if req.path.starts_with("/_mock/") {
+        return handle_control(&mut lock(state), &req);
+    }
+    let route = match route_of(&req) {
+        Some(route) => route,
+        None => return HttpResponse::error(404, "no such endpoint"),
+    };
+    let (status, latency) = {
+        let mut state = lock(state);
+        state.record(Received {
+            time: timestamp(Utc::now()),
+            method: req.method.clone(),
+            path: req.path.clone(),
+            query: req.query.clone(),
+            body: received_body(&req),
+        });
+        state.take_faults(route)
+    };
+    // sleep without holding the lock so other requests aren't held up
+    if let Some(latency) = latency {
+        thread::sleep(Duration::from_millis(latency));
+    }
+    match status {
+        Some(status) => HttpResponse::error(status, "injected failure"),
+        None => handle_api(&mut lock(state), &req, route),
+    }
+}
+
+fn serve_connection(state: &Mutex<State>, stream: TcpStream) {
+    // don't let a client which never finishes its request hold a thread forever
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(READ_TIMEOUT_SECS)));
+    let resp = match read_request(&stream) {
+        Ok(req) => {
+            let (method, path) = (req.method.clone(), req.path.clone());
+            let resp = handle(state, req);
+            eprintln!(
+                "{} {} {} -> {}",
+                timestamp(Utc::now()),
+                method,
+                path,
+                resp.status
+            );
+            resp
+        }
+        Err(e) => HttpResponse::error(400, &e),
+    };
+    if let Err(e) = write_response(&stream, &resp) {
+        eprintln!("could not write response: {}", e);
+    }
+}
+
+fn bind(
+    listen: &str,
+    faults: Vec<Fault>,
+    dump: Option<&str>,
+) -> Result<(TcpListener, Arc<Mutex<State>>), Error> {
+    let dump = match dump {
+        Some(path) => Some(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .map_err(|e| Error::Unknown(format!("Could not open dump file {}: {}", path, e)))?,
+        ),
+        None => None,
+    };
+    let listener = TcpListener::bind(listen)
+        .map_err(|e| Error::Unknown(format!("Could not listen on {}: {}", listen, e)))?;
+    let state = Arc::new(Mutex::new(State {
+        faults,
+        dump,
+        ..State::default()
+    }));
+    Ok((listener, state))
+}
+
+fn accept(listener: TcpListener, state: Arc<Mutex<State>>) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let state = Arc::clone(&state);
+                thread::spawn(move || serve_connection(&state, stream));
+            }
+            Err(e) => eprintln!("could not accept connection: {}", e),
+        }
+    }
+}
+
+// serve the token and namespace endpoints from memory until killed
+pub fn serve(listen: &str, faults: Vec<Fault>, dump: Option<&str>) -> Result<(), Error> {
+    let (listener, state) = bind(listen, faults, dump)?;
+    let addr = listener
+        .local_addr()
+        .map_err(|e| Error::Unknown(e.to_string()))?;
+    eprintln!("mock Platform API listening on http://{}", addr);
+    accept(listener, state);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::{self, BaseUrl};
+    use crate::auth::request_token;
+    use crate::config::HttpConfig;
+    use crate::transport::ReqwestTransport;
+    use crate::types::{NSDefBuilder, OAuthCred};
+
+    // a server on a free port, returning its base URL
+    fn start(faults: Vec<Fault>) -> String {
+        let (listener, state) = bind("127.0.0.1:0", faults, None).unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || accept(listener, state));
+        format!("http://{}", addr)
+    }
+
+    fn creds() -> OAuthCred {
+        OAuthCred::new("scope".to_string(), "client".to_string(), "shh".into())
+    }
+
+    #[test]
+    fn namespace_lifecycle() {
+        let url = start(vec![]);
+        let transport = ReqwestTransport::from_config(&HttpConfig::default()).unwrap();
+        let base = BaseUrl::parse(&url).unwrap();
+        let token = request_token(&transport, &url, "tenant", &creds()).unwrap();
+        let payload = NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("1h")
+            .cluster("cluster")
+            .namespace("app")
+            .build()
+            .unwrap();
+
+        let resp = api::create(&transport, &base, &token, &payload).unwrap();
+        assert_eq!(resp.namespace, "demo-app");
+        let live = api::get(&transport, &base, &token, "demo-app")
+            .unwrap()
+            .unwrap();
+        assert_eq!(live["cluster"], "cluster");
+        api::delete(&transport, &base, &token, "demo-app").unwrap();
+        assert_eq!(
+            api::get(&transport, &base, &token, "demo-app").unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn injected_failure() {
+        let url = start(vec![Fault::parse_failure("create=503x1").unwrap()]);
+        let transport = ReqwestTransport::from_config(&HttpConfig::default()).unwrap();
+        let base = BaseUrl::parse(&url).unwrap();
+        let token = request_token(&transport, &url, "tenant", &creds()).unwrap();
+        let payload = NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("1h")
+            .cluster("cluster")
+            .namespace("app")
+            .build()
+            .unwrap();
+
+        match api::create(&transport, &base, &token, &payload) {
+            Err(Error::Api(503, _)) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        // the fault only applied once
+        assert!(api::create(&transport, &base, &token, &payload).is_ok());
+    }
+
+    fn request(method: &str, path: &str, token: Option<&str>, body: &str) -> HttpRequest {
+        let mut headers = BTreeMap::new();
+        if let Some(token) = token {
+            headers.insert("authorization".to_string(), format!("Bearer {}", token));
+        }
+        HttpRequest {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: BTreeMap::new(),
+            headers,
+            body: body.to_string(),
+        }
+    }
+
+    #[test]
+    fn survives_bad_ttl_and_poisoned_lock() {
+        let (_listener, state) = bind("127.0.0.1:0", vec![], None).unwrap();
+        let form = "grant_type=client_credentials&client_id=c&client_secret=s";
+        let token = request("POST", "/tenant/oauth2/v2.0/token", None, form);
+        assert_eq!(handle(&state, token).status, 200);
+
+        let body = r#"{"productkey":"demo","namespace":"app","ttl":"5é","cluster":"c"}"#;
+        let create = request("POST", "/namespace", Some("mock-token-1"), body);
+        assert_eq!(handle(&state, create).status, 400);
+
+        let poisoner = Arc::clone(&state);
+        let _ = thread::spawn(move || {
+            let _guard = poisoner.lock().unwrap();
+            panic!("poison the state");
+        })
+        .join();
+        assert!(state.is_poisoned());
+        let list = request("GET", "/namespace", Some("mock-token-1"), "");
+        assert_eq!(handle(&state, list).status, 200);
+    }
+}
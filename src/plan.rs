@@ -0,0 +1,184 @@
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::types::NSDef;
+
+// top-level fields we manage, which may be missing from the serialized
+// payload when empty but still present on the live namespace
+const MANAGED_FIELDS: &[&str] = &["labels", "annotations", "vault_config"];
+
+#[derive(Debug, PartialEq)]
+pub enum Change {
+    Added(String, Value),
+    Removed(String, Value),
+    Changed(String, Value, Value),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added(path, new) => write!(f, "+ {}: {}", path, new),
+            Change::Removed(path, old) => write!(f, "- {}: {}", path, old),
+            Change::Changed(path, old, new) => write!(f, "~ {}: {} -> {}", path, old, new),
+        }
+    }
+}
+
+// labels and annotations are lists of {key, value} entries, which are
+// compared by key rather than as a whole
+fn keyed(items: &[Value]) -> Option<Map<String, Value>> {
+    if items.is_empty() {
+        return None;
+    }
+    items
+        .iter()
+        .map(|item| {
+            let key = item.get("key")?.as_str()?;
+            let value = item.get("value").cloned().unwrap_or(Value::Null);
+            Some((key.to_string(), value))
+        })
+        .collect()
+}
+
+fn flatten(prefix: &str, value: &Value, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (k, v) in map {
+                flatten(&format!("{}.{}", prefix, k), v, out);
+            }
+        }
+        Value::Array(items) if keyed(items).is_some() => {
+            for (k, v) in keyed(items).unwrap() {
+                flatten(&format!("{}.{}", prefix, k), &v, out);
+            }
+        }
+        _ => {
+            out.insert(prefix.to_string(), value.clone());
+        }
+    }
+}
+
+fn flatten_fields(fields: &Map<String, Value>) -> BTreeMap<String, Value> {
+    let mut out = BTreeMap::new();
+    for (k, v) in fields {
+        flatten(k, v, &mut out);
+    }
+    out
+}
+
+// compare the payload we would submit against the live namespace. fields
+// only present on the live namespace are ignored unless they are ones we
+// manage, as the API is free to return additional information.
+pub fn diff(desired: &NSDef, live: &Value) -> Vec<Change> {
+    let mut desired = match serde_json::to_value(desired) {
+        Ok(Value::Object(map)) => map,
+        _ => Map::new(),
+    };
+    // the live namespace was looked up by name, which the API reports with
+    // the product key prepended
+    desired.remove("namespace");
+    let live = match live {
+        Value::Object(map) => map
+            .iter()
+            .filter(|(k, _)| desired.contains_key(*k) || MANAGED_FIELDS.contains(&k.as_str()))
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect(),
+        _ => Map::new(),
+    };
+    let desired = flatten_fields(&desired);
+    let mut live = flatten_fields(&live);
+    let mut changes = Vec::new();
+    for (path, new) in desired {
+        match live.remove(&path) {
+            Some(old) if old == new => {}
+            Some(old) => changes.push(Change::Changed(path, old, new)),
+            None => changes.push(Change::Added(path, new)),
+        }
+    }
+    changes.extend(
+        live.into_iter()
+            .map(|(path, old)| Change::Removed(path, old)),
+    );
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ExtraProps, NSDefBuilder};
+    use serde_json::json;
+
+    fn desired() -> NSDef {
+        let mut extra = ExtraProps::new();
+        extra.insert("quota".to_string(), json!({"cpu": 4}));
+        NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("24h")
+            .cluster("cluster")
+            .namespace("app")
+            .labels(vec![
+                ("team".to_string(), "core".to_string()).into(),
+                ("tier".to_string(), "web".to_string()).into(),
+            ])
+            .extra_properties(extra)
+            .build()
+            .unwrap()
+    }
+
+    fn live() -> Value {
+        json!({
+            "productkey": "demo",
+            "ttl": "24h",
+            "cluster": "cluster",
+            "namespace": "demo-app",
+            "labels": [
+                {"key": "team", "value": "core"},
+                {"key": "tier", "value": "web"}
+            ],
+            "quota": {"cpu": 4},
+            "expiry": "2021-08-03T09:49:17Z"
+        })
+    }
+
+    #[test]
+    fn unchanged() {
+        assert_eq!(diff(&desired(), &live()), Vec::new());
+    }
+
+    #[test]
+    fn changed_fields() {
+        let mut live = live();
+        live["ttl"] = json!("7d");
+        live["labels"][1]["value"] = json!("db");
+        live["quota"]["cpu"] = json!(2);
+        assert_eq!(
+            diff(&desired(), &live),
+            vec![
+                Change::Changed("labels.tier".to_string(), json!("db"), json!("web")),
+                Change::Changed("quota.cpu".to_string(), json!(2), json!(4)),
+                Change::Changed("ttl".to_string(), json!("7d"), json!("24h")),
+            ]
+        );
+    }
+
+    #[test]
+    fn added_and_removed_fields() {
+        let mut live = live();
+        live["labels"] = json!([
+            {"key": "team", "value": "core"},
+            {"key": "owner", "value": "someone"}
+        ]);
+        live["annotations"] = json!([{"key": "note", "value": "old"}]);
+        live.as_object_mut().unwrap().remove("quota");
+        assert_eq!(
+            diff(&desired(), &live),
+            vec![
+                Change::Added("labels.tier".to_string(), json!("web")),
+                Change::Added("quota.cpu".to_string(), json!(4)),
+                Change::Removed("annotations.note".to_string(), json!("old")),
+                Change::Removed("labels.owner".to_string(), json!("someone")),
+            ]
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// run `f` over `items` using at most `limit` worker threads, returning the
+// results in the same order as the input.
+pub fn run_concurrently<T, R, F>(items: Vec<T>, limit: usize, f: F) -> Vec<R>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let total = items.len();
+    let workers = limit.max(1).min(total);
+    let queue: Arc<Mutex<VecDeque<(usize, T)>>> =
+        Arc::new(Mutex::new(items.into_iter().enumerate().collect()));
+    let results: Arc<Mutex<Vec<Option<R>>>> =
+        Arc::new(Mutex::new((0..total).map(|_| None).collect()));
+    let f = Arc::new(f);
+    let handles: Vec<_> = (0..workers)
+        .map(|_| {
+            let queue = Arc::clone(&queue);
+            let results = Arc::clone(&results);
+            let f = Arc::clone(&f);
+            thread::spawn(move || loop {
+                let next = queue.lock().unwrap().pop_front();
+                match next {
+                    Some((idx, item)) => {
+                        let res = f(item);
+                        results.lock().unwrap()[idx] = Some(res);
+                    }
+                    None => break,
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().expect("worker thread panicked");
+    }
+    let results = Arc::try_unwrap(results)
+        .unwrap_or_else(|_| panic!("results still shared after workers finished"))
+        .into_inner()
+        .unwrap();
+    results
+        .into_iter()
+        .map(|r| r.expect("missing result from worker"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    // later items finish first, but results keep the input order
+    #[test]
+    fn results_in_input_order() {
+        let items: Vec<u64> = (0..8).collect();
+        let results = run_concurrently(items, 4, |i| {
+            thread::sleep(Duration::from_millis(40 - 5 * i));
+            i * 10
+        });
+        assert_eq!(results, vec![0, 10, 20, 30, 40, 50, 60, 70]);
+    }
+
+    #[test]
+    fn at_most_limit_workers() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let most = Arc::new(AtomicUsize::new(0));
+        let (r, m) = (Arc::clone(&running), Arc::clone(&most));
+        run_concurrently((0..12).collect::<Vec<_>>(), 3, move |_: i32| {
+            let now = r.fetch_add(1, Ordering::SeqCst) + 1;
+            m.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            r.fetch_sub(1, Ordering::SeqCst);
+        });
+        assert!(most.load(Ordering::SeqCst) <= 3);
+    }
+
+    #[test]
+    fn empty_input_and_zero_limit() {
+        assert!(run_concurrently(Vec::<i32>::new(), 4, |i| i).is_empty());
+        assert_eq!(run_concurrently(vec![1, 2], 0, |i| i + 1), vec![2, 3]);
+    }
+}
@@ -0,0 +1,477 @@
+use regex::Regex;
+use serde_json::{json, Map, Value};
+use std::fmt;
+
+use crate::cli::TTL_PATTERN;
+
+const OPENAPI: &str = include_str!("../openapi/platform-api.yaml");
+const DRAFT: &str = "http://json-schema.org/draft-07/schema#";
+// unknown properties this many edits from a known one are likely typos
+const TYPO_DISTANCE: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Kind {
+    Spec,
+    ExtraData,
+    Payload,
+}
+
+// extra properties the Platform API is known to accept. others are passed
+// through as they are, apart from a warning when they look like a typo
+fn known_extra_properties() -> Value {
+    json!({
+        "quota": {
+            "description": "Resource quota for the namespace.",
+            "type": "object",
+            "properties": {
+                "cpu": {
+                    "description": "CPU limit, eg. 4 or '500m'.",
+                    "type": ["number", "string"]
+                },
+                "memory": {
+                    "description": "Memory limit, eg. '8Gi'.",
+                    "type": "string"
+                }
+            },
+            "additionalProperties": false
+        }
+    })
+}
+
+// point '#/components/schemas/...' references at the definitions they are
+// copied to, dropping the codegen extensions
+fn rewrite_refs(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.remove("x-rust-type");
+            if let Some(Value::String(reference)) = map.get_mut("$ref") {
+                if let Some(name) = reference.strip_prefix("#/components/schemas/") {
+                    *reference = format!("#/definitions/{}", name);
+                }
+            }
+            map.values_mut().for_each(rewrite_refs);
+        }
+        Value::Array(items) => items.iter_mut().for_each(rewrite_refs),
+        _ => {}
+    }
+}
+
+// the request schemas of the vendored OpenAPI document, along with the
+// known extra properties and the spec file format
+fn definitions() -> Map<String, Value> {
+    let doc: Value = serde_yaml::from_str(OPENAPI).expect("vendored OpenAPI document is valid");
+    let mut defs = match doc.pointer("/components/schemas") {
+        Some(Value::Object(schemas)) => schemas.clone(),
+        _ => Map::new(),
+    };
+    defs.remove("NamespaceResponse");
+    for schema in defs.values_mut() {
+        rewrite_refs(schema);
+    }
+    if let Some(Value::Object(props)) = defs
+        .get_mut("NamespaceRequest")
+        .and_then(|r| r.get_mut("properties"))
+    {
+        if let Value::Object(extra) = known_extra_properties() {
+            props.extend(extra);
+        }
+    }
+    let properties = defs
+        .get("NamespaceRequest")
+        .and_then(|r| r.get("properties"))
+        .cloned()
+        .unwrap_or_else(|| json!({}));
+    defs.insert(
+        "ExtraData".to_string(),
+        json!({
+            "description": "Extra properties merged into the payload. core fields may only be replaced with --allow-override.",
+            "type": "object",
+            "properties": properties,
+            "additionalProperties": true
+        }),
+    );
+    defs.insert(
+        "NamespaceSpec".to_string(),
+        json!({
+            "description": "A namespace submitted by apply or keepalive.",
+            "type": "object",
+            "required": ["productkey", "name"],
+            "properties": {
+                "productkey": {
+                    "description": "Product key, prepended to the namespace name.",
+                    "type": "string"
+                },
+                "name": {
+                    "description": "Namespace name, appended as suffix to the product key.",
+                    "type": "string"
+                },
+                "ttl": {
+                    "description": "How long the namespace lives for, 1-24h or 1-7d.",
+                    "type": "string",
+                    "pattern": TTL_PATTERN,
+                    "default": "24h"
+                },
+                "cluster": {
+                    "description": "Cluster, otherwise --cluster or PLATFORM_API_CLUSTER.",
+                    "type": "string"
+                },
+                "labels": {
+                    "type": "object",
+                    "additionalProperties": {"type": "string"}
+                },
+                "annotations": {
+                    "type": "object",
+                    "additionalProperties": {"type": "string"}
+                },
+                "vault_service_accounts": {
+                    "description": "Service accounts for vault access, in addition to 'default'.",
+                    "type": "array",
+                    "items": {"type": "string"}
+                },
+                "extra_data": {"$ref": "#/definitions/ExtraData"}
+            },
+            "additionalProperties": false
+        }),
+    );
+    defs
+}
+
+pub fn schema(kind: Kind) -> Value {
+    let (title, root) = match kind {
+        Kind::Spec => (
+            "platformapi-namespace spec file",
+            json!({"anyOf": [
+                {"$ref": "#/definitions/NamespaceSpec"},
+                {"type": "array", "items": {"$ref": "#/definitions/NamespaceSpec"}}
+            ]}),
+        ),
+        Kind::ExtraData => (
+            "platformapi-namespace extra data",
+            json!({"$ref": "#/definitions/ExtraData"}),
+        ),
+        Kind::Payload => (
+            "Platform API namespace payload",
+            json!({"$ref": "#/definitions/NamespaceRequest"}),
+        ),
+    };
+    let mut schema = json!({"$schema": DRAFT, "title": title});
+    if let (Value::Object(schema), Value::Object(root)) = (&mut schema, root) {
+        schema.extend(root);
+        schema.insert("definitions".to_string(), Value::Object(definitions()));
+    }
+    schema
+}
+
+// a problem with the value at a path such as 'quota.cpu' or '[1].extra_data'
+#[derive(Debug, Clone, PartialEq)]
+pub struct Issue {
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.path.is_empty() {
+            write!(f, "top level: {}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Report {
+    pub errors: Vec<Issue>,
+    pub warnings: Vec<Issue>,
+}
+
+impl Report {
+    // one line per error, for the body of an Error
+    pub fn describe(&self) -> String {
+        let mut out = "doesn't match the schema".to_string();
+        for e in &self.errors {
+            out.push_str(&format!("\n  {}", e));
+        }
+        out
+    }
+
+    pub fn warn(&self, source: &str) {
+        for w in &self.warnings {
+            eprintln!("Warning: {}, {}", source, w);
+        }
+    }
+}
+
+fn key_path(path: &str, key: &str) -> String {
+    // dots are escaped as in --set paths
+    let key = key.replace('.', "\\.");
+    if path.is_empty() {
+        key
+    } else {
+        format!("{}.{}", path, key)
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(n) if n.is_f64() => "number",
+        Value::Number(_) => "integer",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+fn allowed_types(schema: &Value) -> Vec<&str> {
+    match schema.get("type") {
+        Some(Value::String(t)) => vec![t.as_str()],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn type_matches(schema: &Value, value: &Value) -> bool {
+    let actual = type_name(value);
+    let allowed = allowed_types(schema);
+    allowed.is_empty()
+        || allowed
+            .iter()
+            .any(|t| *t == actual || (*t == "number" && actual == "integer"))
+}
+
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+fn closest<'a>(known: Option<&'a Map<String, Value>>, key: &str) -> Option<&'a str> {
+    known?
+        .keys()
+        .map(|k| (distance(k, key), k))
+        .filter(|(d, _)| *d <= TYPO_DISTANCE)
+        .min()
+        .map(|(_, k)| k.as_str())
+}
+
+// checks the subset of JSON Schema used by the schemas above
+struct Validator<'a> {
+    root: &'a Value,
+    report: Report,
+}
+
+impl<'a> Validator<'a> {
+    fn new(root: &'a Value) -> Self {
+        Validator {
+            root,
+            report: Report::default(),
+        }
+    }
+
+    fn error(&mut self, path: &str, message: String) {
+        self.report.errors.push(Issue {
+            path: path.to_string(),
+            message,
+        });
+    }
+
+    fn resolve(&self, mut schema: &'a Value) -> &'a Value {
+        while let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            match reference
+                .strip_prefix('#')
+                .and_then(|p| self.root.pointer(p))
+            {
+                Some(target) => schema = target,
+                None => break,
+            }
+        }
+        schema
+    }
+
+    fn validate(&mut self, schema: &'a Value, value: &Value, path: &str) {
+        let schema = self.resolve(schema);
+        if let Some(branches) = schema.get("anyOf").and_then(Value::as_array) {
+            return self.validate_any(branches, value, path);
+        }
+        if !type_matches(schema, value) {
+            let message = format!(
+                "expected {} but found {}",
+                allowed_types(schema).join(" or "),
+                type_name(value)
+            );
+            return self.error(path, message);
+        }
+        if let (Some(pattern), Some(s)) = (
+            schema.get("pattern").and_then(Value::as_str),
+            value.as_str(),
+        ) {
+            if !matches!(Regex::new(pattern), Ok(re) if re.is_match(s)) {
+                self.error(path, format!("'{}' doesn't match {}", s, pattern));
+            }
+        }
+        match value {
+            Value::Object(map) => self.validate_object(schema, map, path),
+            Value::Array(items) => {
+                if let Some(item_schema) = schema.get("items") {
+                    for (i, item) in items.iter().enumerate() {
+                        self.validate(item_schema, item, &format!("{}[{}]", path, i));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // when no branch matches, the errors of the first branch of the right
+    // type are the most useful, eg. those of a spec rather than a list
+    fn validate_any(&mut self, branches: &'a [Value], value: &Value, path: &str) {
+        let mut best = None;
+        for branch in branches {
+            let mut attempt = Validator::new(self.root);
+            attempt.validate(branch, value, path);
+            if attempt.report.errors.is_empty() {
+                self.report.warnings.extend(attempt.report.warnings);
+                return;
+            }
+            if best.is_none() && type_matches(self.resolve(branch), value) {
+                best = Some(attempt.report);
+            }
+        }
+        match best {
+            Some(report) => {
+                self.report.errors.extend(report.errors);
+                self.report.warnings.extend(report.warnings);
+            }
+            None => self.error(
+                path,
+                format!("{} isn't one of the allowed forms", type_name(value)),
+            ),
+        }
+    }
+
+    fn validate_object(&mut self, schema: &'a Value, map: &Map<String, Value>, path: &str) {
+        let known = schema.get("properties").and_then(Value::as_object);
+        for required in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !map.contains_key(required) {
+                self.error(path, format!("missing required property '{}'", required));
+            }
+        }
+        for (key, value) in map {
+            let child = key_path(path, key);
+            if let Some(prop) = known.and_then(|k| k.get(key)) {
+                self.validate(prop, value, &child);
+                continue;
+            }
+            match schema.get("additionalProperties") {
+                Some(Value::Bool(false)) => {
+                    let message = match (closest(known, key), known) {
+                        (Some(close), _) => format!("unknown property, did you mean '{}'?", close),
+                        (None, Some(known)) => format!(
+                            "unknown property, expected one of {}",
+                            known.keys().cloned().collect::<Vec<_>>().join(", ")
+                        ),
+                        (None, None) => "unknown property".to_string(),
+                    };
+                    self.error(&child, message);
+                }
+                Some(extra @ Value::Object(_)) => self.validate(extra, value, &child),
+                _ => {
+                    if let Some(close) = closest(known, key) {
+                        self.report.warnings.push(Issue {
+                            path: child,
+                            message: format!("unknown property, did you mean '{}'?", close),
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn validate(kind: Kind, value: &Value) -> Report {
+    let root = schema(kind);
+    let mut validator = Validator::new(&root);
+    validator.validate(&root, value, "");
+    validator.report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(issues: &[Issue]) -> Vec<String> {
+        issues.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn extra_data_paths() {
+        let report = validate(
+            Kind::ExtraData,
+            &json!({"quota": {"cpuu": 4, "memory": 8}, "qouta": {}, "custom": true}),
+        );
+        assert_eq!(
+            messages(&report.errors),
+            vec![
+                "quota.cpuu: unknown property, did you mean 'cpu'?",
+                "quota.memory: expected string but found integer",
+            ]
+        );
+        assert_eq!(
+            messages(&report.warnings),
+            vec!["qouta: unknown property, did you mean 'quota'?"]
+        );
+    }
+
+    #[test]
+    fn spec_documents() {
+        let good = json!({
+            "productkey": "demo",
+            "name": "app",
+            "ttl": "2h",
+            "labels": {"team": "demo"},
+            "vault_service_accounts": ["db-migrator"],
+            "extra_data": {"quota": {"cpu": "500m"}}
+        });
+        assert!(validate(Kind::Spec, &good).errors.is_empty());
+
+        let bad = json!([good, {"productkey": "demo", "nmae": "db", "ttl": "9d"}]);
+        assert_eq!(
+            messages(&validate(Kind::Spec, &bad).errors),
+            vec![
+                "[1]: missing required property 'name'",
+                "[1].nmae: unknown property, did you mean 'name'?",
+                "[1].ttl: '9d' doesn't match ^(1([hd]|[0-9]h)|2([hd]|[0-4]h)|[3-7][hd]|[89]h)$",
+            ]
+        );
+    }
+
+    #[test]
+    fn payload_schema_from_openapi() {
+        let schema = schema(Kind::Payload);
+        let request = &schema["definitions"]["NamespaceRequest"];
+        for field in crate::types::RESERVED_FIELDS {
+            assert!(request["properties"].get(*field).is_some(), "{}", field);
+        }
+        assert_eq!(
+            request["properties"]["labels"]["$ref"],
+            "#/definitions/Labels"
+        );
+        assert!(schema["definitions"]["Labels"].get("x-rust-type").is_none());
+    }
+}
@@ -0,0 +1,168 @@
+use serde::{Deserialize, Deserializer, Serializer};
+use serde_json::Value;
+use std::fmt;
+use std::sync::RwLock;
+use zeroize::Zeroize;
+
+pub const REDACTED: &str = "REDACTED";
+
+// keys whose values are hidden wherever a payload is logged. a key matches
+// if it contains one of them, ignoring case, so '_token' covers
+// 'access_token' but not 'token_type'
+const DEFAULT_REDACT_KEYS: &[&str] = &[
+    "secret",
+    "password",
+    "credential",
+    "private_key",
+    "api_key",
+    "_token",
+];
+
+// form fields and JSON keys which are always hidden, whatever keys are
+// configured
+const SECRET_FIELDS: &[&str] = &["client_secret", "access_token", "refresh_token", "id_token"];
+
+static REDACT_KEYS: RwLock<Option<Vec<String>>> = RwLock::new(None);
+
+// a credential which never shows up in Debug or Display output, and is
+// overwritten when dropped. the value is only reachable through `expose`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret(value)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(value: &str) -> Self {
+        Secret(value.to_string())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Secret({})", REDACTED)
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", REDACTED)
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        self.0.zeroize();
+    }
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer).map(Secret)
+    }
+}
+
+// secrets aren't Serialize, so sending one has to be asked for with
+// `#[serde(serialize_with = "crate::secret::expose")]`
+pub fn expose<S>(secret: &Secret, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    serializer.serialize_str(secret.expose())
+}
+
+// replaces the default keys, eg. from the config file
+pub fn set_redact_keys(keys: Option<Vec<String>>) {
+    *REDACT_KEYS.write().unwrap() = keys;
+}
+
+pub fn is_sensitive(key: &str) -> bool {
+    matches_keys(key, REDACT_KEYS.read().unwrap().as_deref())
+}
+
+fn matches_keys(key: &str, configured: Option<&[String]>) -> bool {
+    if SECRET_FIELDS.contains(&key) {
+        return true;
+    }
+    let key = key.to_lowercase();
+    match configured {
+        Some(keys) => keys.iter().any(|k| key.contains(&k.to_lowercase())),
+        None => DEFAULT_REDACT_KEYS.iter().any(|k| key.contains(k)),
+    }
+}
+
+// hide the values of sensitive keys anywhere in a payload
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map.iter_mut() {
+                if is_sensitive(k) {
+                    *v = Value::String(REDACTED.to_string());
+                } else {
+                    redact(v);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn secrets_are_redacted() {
+        let secret = Secret::from("hunter2");
+        assert_eq!(format!("{}", secret), REDACTED);
+        assert!(!format!("{:?}", secret).contains("hunter2"));
+        assert_eq!(secret.expose(), "hunter2");
+    }
+
+    #[test]
+    fn redact_payload() {
+        let mut payload = json!({
+            "productkey": "demo",
+            "database": {"Password": "hunter2", "host": "db"},
+            "webhooks": [{"url": "https://hooks", "signing_secret": "abc"}],
+            "token_type": "Bearer",
+            "github_token": "ghp_abc"
+        });
+        redact(&mut payload);
+        assert_eq!(
+            payload,
+            json!({
+                "productkey": "demo",
+                "database": {"Password": REDACTED, "host": "db"},
+                "webhooks": [{"url": "https://hooks", "signing_secret": REDACTED}],
+                "token_type": "Bearer",
+                "github_token": REDACTED
+            })
+        );
+    }
+
+    // configured keys replace the defaults, but not the fixed secret fields
+    #[test]
+    fn configured_keys() {
+        let keys = vec!["Internal".to_string()];
+        for key in &["internal_id", "client_secret", "id_token"] {
+            assert!(matches_keys(key, Some(&keys)), "{}", key);
+        }
+        assert!(!matches_keys("password", Some(&keys)));
+        assert!(matches_keys("password", None));
+    }
+}
@@ -0,0 +1,216 @@
+use klap::{AnnotationMap, Annotations, LabelMap, Labels};
+use serde::Deserialize;
+use serde_yaml::Value;
+use std::fs;
+use std::io::{self, Read};
+
+use crate::cli::validate_ttl;
+use crate::interpolate::Interpolation;
+use crate::schema::{self, Kind};
+use crate::types::{Error, ExtraProps, NSDef, NSDefBuilder, VaultServiceAccounts};
+
+fn default_ttl() -> String {
+    "24h".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NSSpec {
+    pub productkey: String,
+    pub name: String,
+    #[serde(default = "default_ttl")]
+    pub ttl: String,
+    #[serde(default)]
+    pub cluster: Option<String>,
+    #[serde(default)]
+    pub labels: LabelMap,
+    #[serde(default)]
+    pub annotations: AnnotationMap,
+    #[serde(default)]
+    pub vault_service_accounts: Vec<String>,
+    #[serde(default)]
+    pub extra_data: ExtraProps,
+}
+
+impl NSSpec {
+    pub fn full_name(&self) -> String {
+        format!("{}-{}", self.productkey, self.name)
+    }
+
+    pub fn into_nsdef(
+        self,
+        default_cluster: Option<&str>,
+        allow_override: bool,
+    ) -> Result<NSDef, Error> {
+        let full_name = self.full_name();
+        validate_ttl(self.ttl.clone())
+            .map_err(|e| Error::Spec(full_name.clone(), format!("invalid ttl: {}", e)))?;
+        let cluster = match (self.cluster, default_cluster) {
+            (Some(c), _) => c,
+            (None, Some(c)) => c.to_string(),
+            (None, None) => {
+                return Err(Error::Spec(
+                    full_name.clone(),
+                    "no cluster given in spec, via '--cluster' or via env var".to_string(),
+                ))
+            }
+        };
+        let mut vsas = VaultServiceAccounts::new();
+        vsas.extend(self.vault_service_accounts);
+        let labels: Labels = self.labels.into_iter().map(|a| a.into()).collect();
+        let annotations: Annotations = self.annotations.into_iter().map(|a| a.into()).collect();
+        let payload = NSDefBuilder::default()
+            .productkey(self.productkey)
+            .ttl(self.ttl)
+            .cluster(cluster)
+            .namespace(self.name)
+            .labels(labels)
+            .annotations(annotations)
+            .vault_service_accounts(vsas)
+            .extra_properties(self.extra_data)
+            .build()
+            .unwrap();
+        payload
+            .check_overrides(allow_override)
+            .map_err(|e| Error::Spec(full_name, e.to_string()))?;
+        Ok(payload)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum SpecDocument {
+    Many(Vec<NSSpec>),
+    One(Box<NSSpec>),
+}
+
+// parse a (possibly multi-document) yaml string where each document is either
+// a single namespace spec or a list of them.
+pub fn parse_specs(input: &str) -> Result<Vec<NSSpec>, Error> {
+    let mut specs = Vec::new();
+    for (idx, doc) in serde_yaml::Deserializer::from_str(input).enumerate() {
+        // numbered from 1, as in manifest errors
+        let name = format!("document {}", idx + 1);
+        let value =
+            Value::deserialize(doc).map_err(|e| Error::Spec(name.clone(), e.to_string()))?;
+        if value.is_null() {
+            continue;
+        }
+        // checked against the schema first for errors pointing at the key
+        // at fault. anything it can't represent is left to serde
+        if let Ok(json) = serde_json::to_value(&value) {
+            let report = schema::validate(Kind::Spec, &json);
+            report.warn(&format!("spec {}", name));
+            if !report.errors.is_empty() {
+                return Err(Error::Spec(name, report.describe()));
+            }
+        }
+        match serde_yaml::from_value(value).map_err(|e| Error::Spec(name, e.to_string()))? {
+            SpecDocument::Many(s) => specs.extend(s),
+            SpecDocument::One(s) => specs.push(*s),
+        }
+    }
+    Ok(specs)
+}
+
+pub fn read_specs(filename: &str, interp: &Interpolation) -> Result<Vec<NSSpec>, Error> {
+    let mut contents = String::new();
+    if filename == "-" {
+        io::stdin().read_to_string(&mut contents)
+    } else {
+        fs::File::open(filename).and_then(|mut f| f.read_to_string(&mut contents))
+    }
+    .map_err(|e| Error::Option("spec".to_string(), filename.to_string(), e.to_string()))?;
+    parse_specs(&interp.render(filename, contents)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPECS: &str = "\
+productkey: demo
+name: one
+cluster: east
+labels:
+  team: core
+---
+---
+- productkey: demo
+  name: two
+  ttl: 2h
+  vault_service_accounts: [deployer]
+- productkey: demo
+  name: three
+  extra_data:
+    quota:
+      cpu: '1'
+";
+
+    fn names(specs: &[NSSpec]) -> Vec<String> {
+        specs.iter().map(NSSpec::full_name).collect()
+    }
+
+    #[test]
+    fn one_or_many_per_document() {
+        let specs = parse_specs(SPECS).unwrap();
+        assert_eq!(names(&specs), vec!["demo-one", "demo-two", "demo-three"]);
+        assert_eq!(specs[0].ttl, "24h");
+        assert_eq!(specs[0].labels["team"], "core");
+        assert_eq!(specs[1].ttl, "2h");
+        assert!(parse_specs("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn errors_name_the_document() {
+        let bad = "productkey: demo\nname: one\n---\nproductkey: demo\nnmae: two\n";
+        match parse_specs(bad) {
+            Err(Error::Spec(doc, _)) => assert_eq!(doc, "document 2"),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn into_nsdef() {
+        let mut specs = parse_specs(SPECS).unwrap().into_iter();
+        let one = specs
+            .next()
+            .unwrap()
+            .into_nsdef(Some("west"), false)
+            .unwrap();
+        assert_eq!(one.cluster, "east");
+        let two = specs
+            .next()
+            .unwrap()
+            .into_nsdef(Some("west"), false)
+            .unwrap();
+        assert_eq!(two.cluster, "west");
+        assert_eq!(
+            two.vault_service_accounts.service_accounts_string(),
+            "default,deployer"
+        );
+        match specs.next().unwrap().into_nsdef(None, false) {
+            Err(Error::Spec(name, msg)) => {
+                assert_eq!(name, "demo-three");
+                assert!(msg.starts_with("no cluster given"));
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn invalid_ttl_and_overrides() {
+        // the schema catches a bad ttl before into_nsdef would
+        match parse_specs("productkey: demo\nname: a\nttl: forever\n") {
+            Err(Error::Spec(doc, msg)) => {
+                assert_eq!(doc, "document 1");
+                assert!(msg.contains("ttl: 'forever'"), "{}", msg);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+        let spec = |yaml: &str| parse_specs(yaml).unwrap().remove(0);
+        let overriding = "productkey: demo\nname: a\nextra_data:\n  ttl: 48h\n";
+        assert!(spec(overriding).into_nsdef(Some("east"), false).is_err());
+        assert!(spec(overriding).into_nsdef(Some("east"), true).is_ok());
+    }
+}
@@ -0,0 +1,474 @@
+use core::time::Duration;
+use reqwest::blocking::{Client, RequestBuilder};
+use reqwest::header::CONTENT_TYPE;
+use reqwest::{Certificate, Identity, Proxy, Url};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::config::HttpConfig;
+use crate::secret::Secret;
+use crate::types::Error;
+
+// used when no total timeout is configured
+const DEFAULT_TIMEOUT_SECS: u64 = 90;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Method {
+    Get,
+    Post,
+    Delete,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Post => "POST",
+            Method::Delete => "DELETE",
+        }
+    }
+}
+
+// form bodies carry the OAuth client secret
+#[derive(Debug, Clone, PartialEq)]
+pub enum Body {
+    Json(String),
+    Form(Secret),
+}
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: Method,
+    pub url: String,
+    pub bearer: Option<Secret>,
+    pub body: Option<Body>,
+}
+
+impl Request {
+    pub fn new(method: Method, url: String) -> Self {
+        Request {
+            method,
+            url,
+            bearer: None,
+            body: None,
+        }
+    }
+
+    pub fn bearer(mut self, token: impl Into<Secret>) -> Self {
+        self.bearer = Some(token.into());
+        self
+    }
+
+    pub fn body(mut self, body: Body) -> Self {
+        self.body = Some(body);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum TransportError {
+    #[error("request timed out")]
+    Timeout,
+    #[error("{0}")]
+    Other(String),
+}
+
+// the HTTP calls made to the OAuth and Platform APIs go through this so they
+// can be swapped out, eg. for tests
+pub trait Transport: Send + Sync {
+    fn send(&self, req: Request) -> Result<Response, TransportError>;
+
+    // whether responses are replayed from fixtures, in which case nothing
+    // sent needs to be genuine
+    fn is_replay(&self) -> bool {
+        false
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn send(&self, req: Request) -> Result<Response, TransportError> {
+        (**self).send(req)
+    }
+
+    fn is_replay(&self) -> bool {
+        (**self).is_replay()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ReqwestTransport {
+    client: Client,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+}
+
+fn read_file(what: &str, path: &Path) -> Result<Vec<u8>, Error> {
+    fs::read(path)
+        .map_err(|e| Error::Unknown(format!("Could not read {} {}: {}", what, path.display(), e)))
+}
+
+// a CA bundle may hold several PEM certificates, which are added one by one
+fn ca_certificates(path: &Path) -> Result<Vec<Certificate>, Error> {
+    let contents = String::from_utf8(read_file("CA certificate", path)?).map_err(|_| {
+        Error::Unknown(format!(
+            "CA certificate {} is not PEM encoded",
+            path.display()
+        ))
+    })?;
+    const END: &str = "-----END CERTIFICATE-----";
+    let certs = contents
+        .split_inclusive(END)
+        .filter(|pem| pem.contains(END))
+        .map(|pem| {
+            Certificate::from_pem(pem.as_bytes()).map_err(|e| {
+                Error::Unknown(format!(
+                    "Invalid CA certificate in {}: {}",
+                    path.display(),
+                    e
+                ))
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if certs.is_empty() {
+        return Err(Error::Unknown(format!(
+            "No PEM certificates found in {}",
+            path.display()
+        )));
+    }
+    Ok(certs)
+}
+
+// 'example.com' and '.example.com' both match example.com and its subdomains
+fn bypasses_proxy(no_proxy: &[String], host: &str) -> bool {
+    no_proxy.iter().any(|entry| {
+        let entry = entry.trim_start_matches('.');
+        entry == "*" || host == entry || host.ends_with(&format!(".{}", entry))
+    })
+}
+
+fn proxy(url: &str, no_proxy: &[String]) -> Result<Proxy, Error> {
+    let url = Url::parse(url)
+        .map_err(|e| Error::Option("proxy".to_string(), url.to_string(), e.to_string()))?;
+    let no_proxy = no_proxy.to_vec();
+    Ok(Proxy::custom(move |target| match target.host_str() {
+        Some(host) if bypasses_proxy(&no_proxy, host) => None,
+        _ => Some(url.clone()),
+    }))
+}
+
+impl ReqwestTransport {
+    pub fn from_config(config: &HttpConfig) -> Result<Self, Error> {
+        let mut builder = Client::builder().timeout(Duration::from_secs(
+            config.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS),
+        ));
+        if let Some(secs) = config.connect_timeout_secs {
+            builder = builder.connect_timeout(Duration::from_secs(secs));
+        }
+        if let Some(url) = &config.proxy {
+            builder = builder.proxy(proxy(url, &config.no_proxy)?);
+        }
+        for path in &config.ca_certs {
+            for cert in ca_certificates(path)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        if let Some(path) = &config.client_cert {
+            let contents = read_file("client certificate", path)?;
+            if is_pem(path, &contents) {
+                return Err(Error::Unknown(format!(
+                    "Client certificate {} is PEM encoded, but only PKCS#12 is supported. \
+                     Convert it with 'openssl pkcs12 -export -in cert.pem -inkey key.pem -out cert.p12'",
+                    path.display()
+                )));
+            }
+            let password = config
+                .client_cert_password
+                .as_ref()
+                .map_or("", Secret::expose);
+            let identity = Identity::from_pkcs12_der(&contents, password).map_err(|e| {
+                Error::Unknown(format!(
+                    "Invalid PKCS#12 client certificate {}: {}",
+                    path.display(),
+                    e
+                ))
+            })?;
+            builder = builder.identity(identity);
+        }
+        let client = builder
+            .build()
+            .map_err(|e| Error::Unknown(format!("Could not configure HTTP client: {}", e)))?;
+        Ok(ReqwestTransport {
+            client,
+            connect_timeout: config.connect_timeout_secs.map(Duration::from_secs),
+            read_timeout: config.read_timeout_secs.map(Duration::from_secs),
+        })
+    }
+}
+
+fn is_pem(path: &Path, contents: &[u8]) -> bool {
+    let pem_header = b"-----BEGIN ";
+    matches!(path.extension().and_then(|e| e.to_str()), Some("pem"))
+        || contents.windows(pem_header.len()).any(|w| w == pem_header)
+}
+
+fn transport_error(e: reqwest::Error) -> TransportError {
+    if e.is_timeout() {
+        TransportError::Timeout
+    } else {
+        TransportError::Other(e.to_string())
+    }
+}
+
+enum Event {
+    Status(u16),
+    Chunk(Vec<u8>),
+    Done,
+    Failed(TransportError),
+}
+
+// reqwest only has connect and total timeouts, so a read timeout is enforced
+// by receiving the response from a worker thread. `first` is allowed for the
+// status to arrive and `read` between chunks of the body. a worker which is
+// given up on still stops at the total timeout.
+fn send_with_read_timeout(
+    builder: RequestBuilder,
+    first: Duration,
+    read: Duration,
+) -> Result<Response, TransportError> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut resp = match builder.send() {
+            Ok(resp) => resp,
+            Err(e) => {
+                let _ = tx.send(Event::Failed(transport_error(e)));
+                return;
+            }
+        };
+        if tx.send(Event::Status(resp.status().as_u16())).is_err() {
+            return;
+        }
+        let mut buf = [0; 8192];
+        loop {
+            let event = match resp.read(&mut buf) {
+                Ok(0) => Event::Done,
+                Ok(n) => Event::Chunk(buf[..n].to_vec()),
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    Event::Failed(TransportError::Timeout)
+                }
+                Err(e) => Event::Failed(TransportError::Other(e.to_string())),
+            };
+            let more = matches!(event, Event::Chunk(_));
+            if tx.send(event).is_err() || !more {
+                return;
+            }
+        }
+    });
+    let recv = |wait| {
+        rx.recv_timeout(wait).map_err(|e| match e {
+            mpsc::RecvTimeoutError::Timeout => TransportError::Timeout,
+            mpsc::RecvTimeoutError::Disconnected => {
+                TransportError::Other("HTTP client thread exited".to_string())
+            }
+        })
+    };
+    let status = match recv(first)? {
+        Event::Status(status) => status,
+        Event::Failed(e) => return Err(e),
+        Event::Chunk(_) | Event::Done => unreachable!("body before status"),
+    };
+    let mut body = Vec::new();
+    loop {
+        match recv(read)? {
+            Event::Chunk(chunk) => body.extend(chunk),
+            Event::Done => break,
+            Event::Failed(e) => return Err(e),
+            Event::Status(_) => unreachable!("status sent twice"),
+        }
+    }
+    Ok(Response {
+        status,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+// streams a secret body to the client, so it isn't copied into a string
+// which outlives the request without being zeroed
+struct SecretReader(Secret, usize);
+
+impl Read for SecretReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let rest = &self.0.expose().as_bytes()[self.1..];
+        let n = rest.len().min(buf.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.1 += n;
+        Ok(n)
+    }
+}
+
+impl Transport for ReqwestTransport {
+    fn send(&self, req: Request) -> Result<Response, TransportError> {
+        let mut builder = match req.method {
+            Method::Get => self.client.get(&req.url),
+            Method::Post => self.client.post(&req.url),
+            Method::Delete => self.client.delete(&req.url),
+        };
+        if let Some(token) = req.bearer {
+            builder = builder.bearer_auth(token.expose());
+        }
+        builder = match req.body {
+            Some(Body::Json(body)) => builder.header(CONTENT_TYPE, "application/json").body(body),
+            Some(Body::Form(body)) => {
+                let len = body.expose().len() as u64;
+                builder
+                    .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+                    .body(reqwest::blocking::Body::sized(SecretReader(body, 0), len))
+            }
+            None => builder,
+        };
+        if let Some(read) = self.read_timeout {
+            // the status can't arrive before the connection is made
+            let first = read + self.connect_timeout.unwrap_or(read);
+            return send_with_read_timeout(builder, first, read);
+        }
+        let resp = builder.send().map_err(transport_error)?;
+        let status = resp.status().as_u16();
+        let body = resp.text().map_err(transport_error)?;
+        Ok(Response { status, body })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::time::Instant;
+
+    // serves one response, stalling for `stall` after the headers
+    fn serve_once(stall: Duration) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 4096];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 4\r\n\r\nok");
+            let _ = stream.flush();
+            thread::sleep(stall);
+            let _ = stream.write_all(b"!!");
+        });
+        format!("http://{}/", addr)
+    }
+
+    fn transport(read_timeout_secs: u64) -> ReqwestTransport {
+        ReqwestTransport::from_config(&HttpConfig {
+            read_timeout_secs: Some(read_timeout_secs),
+            ..HttpConfig::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn read_timeout() {
+        let url = serve_once(Duration::from_secs(5));
+        let started = Instant::now();
+        match transport(1).send(Request::new(Method::Get, url)) {
+            Err(TransportError::Timeout) => {}
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(started.elapsed() < Duration::from_secs(4));
+    }
+
+    #[test]
+    fn read_within_timeout() {
+        let url = serve_once(Duration::from_millis(100));
+        let resp = transport(5).send(Request::new(Method::Get, url)).unwrap();
+        assert_eq!((resp.status, resp.body.as_str()), (200, "ok!!"));
+    }
+
+    #[test]
+    fn pem_client_certificate() {
+        let path = std::env::temp_dir().join(format!("client-{}.p12", std::process::id()));
+        fs::write(
+            &path,
+            "-----BEGIN CERTIFICATE-----\nabc\n-----END CERTIFICATE-----\n",
+        )
+        .unwrap();
+        let result = ReqwestTransport::from_config(&HttpConfig {
+            client_cert: Some(path.clone()),
+            ..HttpConfig::default()
+        });
+        fs::remove_file(&path).unwrap();
+        match result {
+            Err(Error::Unknown(msg)) => {
+                assert!(msg.contains("only PKCS#12 is supported"), "{}", msg)
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use super::*;
+    use std::collections::VecDeque;
+    use std::sync::Mutex;
+
+    // replays canned responses in order and records the requests made
+    #[derive(Default)]
+    pub struct FakeTransport {
+        responses: Mutex<VecDeque<Result<Response, TransportError>>>,
+        requests: Mutex<Vec<Request>>,
+    }
+
+    impl FakeTransport {
+        pub fn new() -> Self {
+            FakeTransport::default()
+        }
+
+        pub fn respond(self, status: u16, body: &str) -> Self {
+            self.responses.lock().unwrap().push_back(Ok(Response {
+                status,
+                body: body.to_string(),
+            }));
+            self
+        }
+
+        pub fn fail(self, err: TransportError) -> Self {
+            self.responses.lock().unwrap().push_back(Err(err));
+            self
+        }
+
+        pub fn requests(&self) -> Vec<Request> {
+            self.requests.lock().unwrap().clone()
+        }
+    }
+
+    impl Transport for FakeTransport {
+        fn send(&self, req: Request) -> Result<Response, TransportError> {
+            self.requests.lock().unwrap().push(req);
+            self.responses
+                .lock()
+                .unwrap()
+                .pop_front()
+                .expect("no more canned responses in FakeTransport")
+        }
+    }
+}
@@ -1,16 +1,22 @@
 use derive_builder::*;
 use klap::{Annotations, Labels};
+use log::warn;
+use serde::ser::Error as _;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
 use std::fmt;
+use std::path::PathBuf;
+
+use crate::generated::{NamespaceRequest, VaultConfig, NAMESPACE_REQUEST_FIELDS};
+use crate::secret::Secret;
 
 #[derive(Debug, Deserialize)]
 pub struct Token {
     #[serde(rename(deserialize = "token_type"))]
     type_: String,
     #[serde(rename(deserialize = "access_token"))]
-    value: String,
+    value: Secret,
 }
 
 impl Token {
@@ -19,10 +25,10 @@ impl Token {
     }
 }
 
-// Display used for .bearer_auth()
-impl fmt::Display for Token {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.value)
+// used for the bearer of a Request
+impl From<&Token> for Secret {
+    fn from(token: &Token) -> Self {
+        token.value.clone()
     }
 }
 
@@ -82,21 +88,22 @@ impl std::iter::Extend<String> for VaultServiceAccounts {
     }
 }
 
-impl Serialize for VaultServiceAccounts {
-    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
-    where
-        S: serde::Serializer,
-    {
-        use serde::ser::SerializeStruct;
-        let mut s = serializer.serialize_struct("vault_config", 1)?;
-        s.serialize_field("service_account_name", &self.service_accounts_string())?;
-        s.end()
+impl VaultServiceAccounts {
+    pub fn to_config(&self) -> VaultConfig {
+        VaultConfig {
+            service_account_name: self.service_accounts_string(),
+        }
     }
 }
 
 pub type ExtraProps = HashMap<String, Value>;
 
-#[derive(Debug, Serialize, Builder)]
+// keys of the payload set by NSDef itself, which extra properties may only
+// replace when explicitly allowed. they are the properties of the request
+// schema in the vendored OpenAPI document.
+pub const RESERVED_FIELDS: &[&str] = NAMESPACE_REQUEST_FIELDS;
+
+#[derive(Debug, Builder)]
 #[builder(setter(into))]
 pub struct NSDef {
     pub productkey: String,
@@ -104,36 +111,107 @@ pub struct NSDef {
     pub cluster: String,
     pub namespace: String,
     #[builder(default)]
-    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub labels: Labels,
     #[builder(default)]
-    #[serde(skip_serializing_if = "Vec::is_empty")]
     pub annotations: Annotations,
     #[builder(default)]
-    #[serde(
-        skip_serializing_if = "VaultServiceAccounts::is_empty",
-        rename = "vault_config"
-    )]
     pub vault_service_accounts: VaultServiceAccounts,
     #[builder(default)]
-    #[serde(flatten)]
     pub extra_properties: ExtraProps,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct NSResponse {
-    pub message: String,
-    pub namespace: String,
-    pub expiry: String,
+impl NSDef {
+    // extra properties which would replace one of the core fields
+    pub fn overridden_fields(&self) -> Vec<&str> {
+        RESERVED_FIELDS
+            .iter()
+            .filter(|f| self.extra_properties.contains_key(**f))
+            .copied()
+            .collect()
+    }
+
+    pub fn check_overrides(&self, allow_override: bool) -> Result<(), Error> {
+        let overridden = self.overridden_fields();
+        if overridden.is_empty() {
+            Ok(())
+        } else if allow_override {
+            warn!(
+                "extra data replaces core fields of {}-{}: {}",
+                self.productkey,
+                self.namespace,
+                overridden.join(", ")
+            );
+            Ok(())
+        } else {
+            Err(Error::ReservedFields(overridden.join(", ")))
+        }
+    }
+
+    // the payload as the generated request type. extra properties replacing
+    // a core field can't be typed, so they're left to `Serialize`.
+    pub fn to_request(&self) -> NamespaceRequest {
+        NamespaceRequest {
+            productkey: self.productkey.clone(),
+            ttl: self.ttl.clone(),
+            cluster: self.cluster.clone(),
+            namespace: self.namespace.clone(),
+            labels: Some(self.labels.clone()).filter(|l| !l.is_empty()),
+            annotations: Some(self.annotations.clone()).filter(|a| !a.is_empty()),
+            vault_config: Some(&self.vault_service_accounts)
+                .filter(|v| !v.is_empty())
+                .map(VaultServiceAccounts::to_config),
+            additional_properties: self
+                .extra_properties
+                .iter()
+                .filter(|(k, _)| !RESERVED_FIELDS.contains(&k.as_str()))
+                .map(|(k, v)| (k.clone(), v.clone()))
+                .collect(),
+        }
+    }
+}
+
+// serialized through the generated request type, with extra properties
+// replacing any core field of the same name rather than emitting a duplicate
+// key
+impl Serialize for NSDef {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut payload = serde_json::to_value(self.to_request()).map_err(S::Error::custom)?;
+        if let Value::Object(map) = &mut payload {
+            for field in self.overridden_fields() {
+                map.insert(field.to_string(), self.extra_properties[field].clone());
+            }
+        }
+        payload.serialize(serializer)
+    }
 }
 
+pub use crate::generated::NamespaceResponse as NSResponse;
+
+// fields beyond the schema which are worth showing by default, along with
+// any link such as 'dashboard_url'. the rest only appear with --output
+const NOTABLE_RESPONSE_FIELDS: &[&str] = &["status", "owner", "quota"];
+
 impl fmt::Display for NSResponse {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
             "message: {}\nnamespace: {}\nexpiry: {}",
             self.message, self.namespace, self.expiry
-        )
+        )?;
+        for (k, v) in &self.additional_properties {
+            if !NOTABLE_RESPONSE_FIELDS.contains(&k.as_str()) && !k.ends_with("url") {
+                continue;
+            }
+            match v {
+                Value::String(s) => write!(f, "\n{}: {}", k, s)?,
+                Value::Null => {}
+                other => write!(f, "\n{}: {}", k, other)?,
+            }
+        }
+        Ok(())
     }
 }
 
@@ -141,12 +219,13 @@ impl fmt::Display for NSResponse {
 pub struct OAuthCred {
     scope: String,
     client_id: String,
-    client_secret: String,
+    #[serde(serialize_with = "crate::secret::expose")]
+    client_secret: Secret,
     grant_type: String,
 }
 
 impl OAuthCred {
-    pub fn new(scope: String, client_id: String, client_secret: String) -> Self {
+    pub fn new(scope: String, client_id: String, client_secret: Secret) -> Self {
         OAuthCred {
             scope,
             client_id,
@@ -156,6 +235,13 @@ impl OAuthCred {
     }
 }
 
+fn last_error(error: &Option<String>) -> String {
+    match error {
+        Some(e) => format!(", last error: {}", e),
+        None => String::new(),
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("Environment Error: {0}")]
@@ -166,8 +252,26 @@ pub enum Error {
     Api(u16, String),
     #[error("Timeout calling PlatformAPI")]
     APITimeout,
+    #[error("Timed out after {1}s waiting for namespace {0} to become Active{}", last_error(.2))]
+    WaitTimeout(String, u64, Option<String>),
+    #[error("Error with kubeconfig {}: {1}", .0.display())]
+    Kubeconfig(PathBuf, String),
+    #[error("Error with ledger {}: {1}", .0.display())]
+    Ledger(PathBuf, String),
+    #[error("Error with config file {}: {1}", .0.display())]
+    Config(PathBuf, String),
     #[error("Error in {0} option value '{1}': {2}")]
     Option(String, String, String),
+    #[error("Error in spec for {0}: {1}")]
+    Spec(String, String),
+    #[error("Error in {0}: {1}")]
+    Schema(String, String),
+    #[error("Extra data sets core namespace fields ({0}), pass --allow-override to replace them")]
+    ReservedFields(String),
+    #[error("Platform API version {1} doesn't support fields sent for {0}: {2}")]
+    UnsupportedFields(String, String, String),
+    #[error("Error interpolating {0}: {1}")]
+    Interpolation(String, String),
     #[error("{0}")]
     Unknown(String),
 }
@@ -186,3 +290,74 @@ impl fmt::Debug for ExitError {
         write!(f, "{}", self.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn nsdef(extra: Value) -> NSDef {
+        let extra = match extra {
+            Value::Object(map) => map.into_iter().collect(),
+            _ => ExtraProps::new(),
+        };
+        NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("24h")
+            .cluster("cluster")
+            .namespace("app")
+            .labels(klap::labels_from_str_either("team=core").unwrap())
+            .extra_properties(extra)
+            .build()
+            .unwrap()
+    }
+
+    // a deserialized Value would hide duplicates, so count keys in the text
+    #[test]
+    fn extra_fields_replace_core_fields() {
+        let payload = nsdef(json!({"ttl": "48h", "labels": [], "quota": {"cpu": "1"}}));
+        let text = serde_json::to_string(&payload).unwrap();
+        for key in &["\"ttl\":", "\"labels\":", "\"quota\":", "\"productkey\":"] {
+            assert_eq!(text.matches(key).count(), 1, "{} in {}", key, text);
+        }
+        assert_eq!(
+            serde_json::from_str::<Value>(&text).unwrap(),
+            json!({
+                "productkey": "demo",
+                "ttl": "48h",
+                "cluster": "cluster",
+                "namespace": "app",
+                "labels": [],
+                "quota": {"cpu": "1"}
+            })
+        );
+    }
+
+    #[test]
+    fn empty_core_fields_are_left_out() {
+        let payload = NSDefBuilder::default()
+            .productkey("demo")
+            .ttl("24h")
+            .cluster("cluster")
+            .namespace("app")
+            .vault_service_accounts(VaultServiceAccounts::new_no_default())
+            .build()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&payload).unwrap(),
+            json!({"productkey": "demo", "ttl": "24h", "cluster": "cluster", "namespace": "app"})
+        );
+    }
+
+    #[test]
+    fn overrides() {
+        assert!(nsdef(json!({"quota": {}})).check_overrides(false).is_ok());
+        let payload = nsdef(json!({"ttl": "48h", "cluster": "other", "quota": {}}));
+        assert_eq!(payload.overridden_fields(), vec!["ttl", "cluster"]);
+        match payload.check_overrides(false) {
+            Err(Error::ReservedFields(fields)) => assert_eq!(fields, "ttl, cluster"),
+            other => panic!("unexpected result {:?}", other),
+        }
+        assert!(payload.check_overrides(true).is_ok());
+    }
+}
@@ -0,0 +1,245 @@
+use core::time::Duration;
+use log::{info, warn};
+use reqwest::blocking::Client;
+use reqwest::{Certificate, StatusCode};
+use serde_json::Value;
+use std::fs;
+use std::thread;
+use std::time::Instant;
+
+use crate::api::{self, BaseUrl};
+use crate::kubeconfig::{kubeconfig_path, Kubeconfig};
+use crate::secret::Secret;
+use crate::transport::Transport;
+use crate::types::{Error, Token};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+fn is_active(status: Option<&Value>) -> bool {
+    match status {
+        Some(Value::String(s)) => s == "Active",
+        Some(Value::Object(o)) => o.get("phase").and_then(Value::as_str) == Some("Active"),
+        _ => false,
+    }
+}
+
+pub struct KubeClient {
+    client: Client,
+    server: String,
+    token: Option<Secret>,
+}
+
+impl KubeClient {
+    pub fn from_kubeconfig(path: Option<&str>, context: Option<&str>) -> Result<Self, Error> {
+        let path = kubeconfig_path(path)?;
+        let config = Kubeconfig::load(&path)?;
+        let ctx = config.context(context)?;
+        let cluster = config.cluster(&ctx.context.cluster)?;
+        let user = config.user(&ctx.context.user)?;
+        let mut builder = Client::builder();
+        let ca = match (
+            &cluster.certificate_authority_data,
+            &cluster.certificate_authority,
+        ) {
+            (Some(data), _) => Some(base64::decode(data.trim()).map_err(|e| {
+                Error::Kubeconfig(
+                    path.clone(),
+                    format!("invalid certificate-authority-data: {}", e),
+                )
+            })?),
+            (None, Some(file)) => {
+                Some(fs::read(file).map_err(|e| Error::Kubeconfig(path.clone(), e.to_string()))?)
+            }
+            (None, None) => None,
+        };
+        if let Some(ca) = ca {
+            let cert = Certificate::from_pem(&ca)
+                .map_err(|e| Error::Kubeconfig(path.clone(), e.to_string()))?;
+            builder = builder.add_root_certificate(cert);
+        }
+        if cluster.insecure_skip_tls_verify == Some(true) {
+            builder = builder.danger_accept_invalid_certs(true);
+        }
+        let token = match (&user.token, &user.token_file) {
+            (Some(t), _) => Some(Secret::from(t.as_str())),
+            (None, Some(file)) => Some(Secret::from(
+                fs::read_to_string(file)
+                    .map_err(|e| Error::Kubeconfig(path.clone(), e.to_string()))?
+                    .trim(),
+            )),
+            (None, None) => None,
+        };
+        if token.is_none() && !user.other.is_empty() {
+            return Err(Error::Kubeconfig(
+                path,
+                format!(
+                    "user '{}' uses an authentication method other than a bearer token, which is not supported",
+                    ctx.context.user
+                ),
+            ));
+        }
+        let client = builder
+            .build()
+            .map_err(|e| Error::Unknown(format!("Could not build kubernetes client: {}", e)))?;
+        Ok(KubeClient {
+            client,
+            server: cluster.server.trim_end_matches('/').to_string(),
+            token,
+        })
+    }
+
+    fn namespace_active(&self, namespace: &str) -> Result<bool, Error> {
+        let url = format!("{}/api/v1/namespaces/{}", self.server, namespace);
+        let mut req = self.client.get(&url);
+        if let Some(token) = &self.token {
+            req = req.bearer_auth(token.expose());
+        }
+        let resp = req
+            .send()
+            .map_err(|e| Error::Unknown(format!("Error calling kubernetes API: {}", e)))?;
+        let status = resp.status();
+        let body = resp.text().unwrap_or_default();
+        if status == StatusCode::NOT_FOUND {
+            Ok(false)
+        } else if status.is_success() {
+            let ns: Value = serde_json::from_str(&body).map_err(|e| {
+                Error::Unknown(format!("Error decoding kubernetes API response: {}", e))
+            })?;
+            Ok(is_active(ns.get("status")))
+        } else {
+            Err(Error::Unknown(format!(
+                "Error from kubernetes API, status code: {}\n{}",
+                status.as_u16(),
+                body
+            )))
+        }
+    }
+}
+
+pub enum Readiness<'a> {
+    PlatformApi {
+        transport: &'a dyn Transport,
+        base: &'a BaseUrl,
+        token: &'a Token,
+    },
+    Kubernetes(KubeClient),
+}
+
+impl<'a> Readiness<'a> {
+    fn check(&self, namespace: &str) -> Result<bool, Error> {
+        match self {
+            Readiness::PlatformApi {
+                transport,
+                base,
+                token,
+            } => Ok(api::get(*transport, base, token, namespace)?
+                .map(|ns| is_active(ns.get("status")))
+                .unwrap_or(false)),
+            Readiness::Kubernetes(kube) => kube.namespace_active(namespace),
+        }
+    }
+
+    // poll until the namespace reports itself as Active or the timeout
+    // elapses. errors are retried, the namespace object may not exist yet or
+    // the API may be briefly unavailable.
+    pub fn wait(&self, namespace: &str, timeout: Duration) -> Result<(), Error> {
+        self.poll(namespace, timeout, POLL_INTERVAL)
+    }
+
+    fn poll(&self, namespace: &str, timeout: Duration, interval: Duration) -> Result<(), Error> {
+        let start = Instant::now();
+        let mut last_error = None;
+        loop {
+            match self.check(namespace) {
+                Ok(true) => {
+                    info!("namespace {} is active", namespace);
+                    return Ok(());
+                }
+                Ok(false) => info!("namespace {} not active yet, waiting", namespace),
+                Err(e) => {
+                    warn!("could not check namespace {}, retrying: {}", namespace, e);
+                    last_error = Some(e.to_string());
+                }
+            }
+            let elapsed = start.elapsed();
+            if elapsed >= timeout {
+                return Err(Error::WaitTimeout(
+                    namespace.to_string(),
+                    timeout.as_secs(),
+                    last_error,
+                ));
+            }
+            thread::sleep(interval.min(timeout - elapsed));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::fake::FakeTransport;
+    use crate::transport::TransportError;
+    use serde_json::json;
+
+    fn token() -> Token {
+        serde_json::from_str(r#"{"token_type": "Bearer", "access_token": "abc"}"#).unwrap()
+    }
+
+    #[test]
+    fn active_status() {
+        assert!(!is_active(None));
+        assert!(!is_active(Some(&json!("Pending"))));
+        assert!(!is_active(Some(&json!({"phase": "Pending"}))));
+        assert!(!is_active(Some(&json!({}))));
+        assert!(is_active(Some(&json!("Active"))));
+        assert!(is_active(Some(&json!({"phase": "Active"}))));
+    }
+
+    #[test]
+    fn retries_until_active() {
+        let transport = FakeTransport::new()
+            .fail(TransportError::Other("connection reset".to_string()))
+            .respond(404, "")
+            .respond(503, "unavailable")
+            .respond(200, r#"{"status": {"phase": "Pending"}}"#)
+            .respond(200, r#"{"status": {"phase": "Active"}}"#);
+        let base = BaseUrl::parse("api.example.com").unwrap();
+        let token = token();
+        let readiness = Readiness::PlatformApi {
+            transport: &transport,
+            base: &base,
+            token: &token,
+        };
+        readiness
+            .poll(
+                "demo-app",
+                Duration::from_secs(10),
+                Duration::from_millis(1),
+            )
+            .unwrap();
+        assert_eq!(transport.requests().len(), 5);
+    }
+
+    #[test]
+    fn times_out_with_last_error() {
+        let mut transport = FakeTransport::new();
+        for _ in 0..1000 {
+            transport = transport.respond(503, "unavailable");
+        }
+        let base = BaseUrl::parse("api.example.com").unwrap();
+        let token = token();
+        let readiness = Readiness::PlatformApi {
+            transport: &transport,
+            base: &base,
+            token: &token,
+        };
+        match readiness.poll(
+            "demo-app",
+            Duration::from_millis(20),
+            Duration::from_millis(5),
+        ) {
+            Err(e @ Error::WaitTimeout(..)) => assert!(e.to_string().contains("unavailable")),
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+}